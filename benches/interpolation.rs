@@ -0,0 +1,72 @@
+// Compares the table-driven interpolators against computing the sinc on the fly
+// Run with `cargo bench --bench interpolation`
+
+use std::{f32::consts::PI, hint::black_box, time::Instant};
+
+use modplayer::engine::interpolation::Interpolation;
+
+const SAMPLES: usize = 1 << 20;
+
+// What the player used to do per output sample
+fn naive_sinc(taps: &[f32], fraction: f32) -> f32 {
+    let center = (taps.len() - 1) / 2;
+    taps.iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let t = (i as f32 - center as f32 - fraction) * PI;
+            if t.abs() < 0.0001 {
+                *x
+            } else {
+                x * t.sin() / t
+            }
+        })
+        .sum()
+}
+
+fn bench(name: &str, taps: usize, interpolate: impl Fn(&[f32], f32) -> f32) {
+    let audio: Vec<f32> = (0..SAMPLES + taps)
+        .map(|i| (i as f32 * 0.01).sin() * 32767.0)
+        .collect();
+    let step = 0.7331;
+
+    let start = Instant::now();
+    let mut position = 0.0f64;
+    let mut sum = 0.0;
+    while (position as usize) < SAMPLES {
+        let index = position as usize;
+        sum += interpolate(
+            &audio[index..index + taps],
+            (position - index as f64) as f32,
+        );
+        position += step;
+    }
+    black_box(sum);
+
+    let elapsed = start.elapsed();
+    let count = (SAMPLES as f64 / step) as u32;
+    println!(
+        "{name:>12}: {:8.2} ns/sample",
+        elapsed.as_nanos() as f64 / count as f64
+    );
+}
+
+fn main() {
+    Interpolation::prepare();
+
+    for mode in [
+        Interpolation::None,
+        Interpolation::Linear,
+        Interpolation::Cubic,
+        Interpolation::Sinc8,
+        Interpolation::Sinc16,
+        Interpolation::Sinc32,
+    ] {
+        bench(&format!("{mode:?}"), mode.taps(), |taps, f| {
+            mode.interpolate(taps, f)
+        });
+    }
+
+    for taps in [8, 16, 32] {
+        bench(&format!("NaiveSinc{taps}"), taps, naive_sinc);
+    }
+}
@@ -0,0 +1,125 @@
+use std::sync::atomic::{AtomicI8, AtomicU32, AtomicU64, Ordering};
+
+// Mute, solo and gain of the 64 pattern channels, shared between the host and the player.
+// Everything is atomic, so it can be changed from any thread while the audio callback runs.
+pub struct ChannelControls {
+    muted: AtomicU64,       // One bit per channel
+    soloed: AtomicU64,      // When any is set, only those channels play
+    gains: [AtomicU32; 64], // f32 bits
+}
+
+fn bit(channel: usize) -> u64 {
+    assert!(channel < 64, "there are only 64 channels");
+    1 << channel
+}
+
+impl Default for ChannelControls {
+    fn default() -> Self {
+        ChannelControls {
+            muted: AtomicU64::new(0),
+            soloed: AtomicU64::new(0),
+            gains: std::array::from_fn(|_| AtomicU32::new(1.0f32.to_bits())),
+        }
+    }
+}
+
+impl ChannelControls {
+    pub fn set_muted(&self, channel: usize, muted: bool) {
+        if muted {
+            self.muted.fetch_or(bit(channel), Ordering::Relaxed);
+        } else {
+            self.muted.fetch_and(!bit(channel), Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_muted(&self, channel: usize) -> bool {
+        self.muted.load(Ordering::Relaxed) & bit(channel) != 0
+    }
+
+    pub fn set_solo(&self, channel: usize, solo: bool) {
+        if solo {
+            self.soloed.fetch_or(bit(channel), Ordering::Relaxed);
+        } else {
+            self.soloed.fetch_and(!bit(channel), Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_solo(&self, channel: usize) -> bool {
+        self.soloed.load(Ordering::Relaxed) & bit(channel) != 0
+    }
+
+    // Linear, 1 leaves the channel as the module has it
+    pub fn set_gain(&self, channel: usize, gain: f32) {
+        self.gains[channel].store(gain.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn gain(&self, channel: usize) -> f32 {
+        f32::from_bits(self.gains[channel].load(Ordering::Relaxed))
+    }
+
+    // Unmutes, unsolos and resets the gain of every channel
+    pub fn clear(&self) {
+        self.muted.store(0, Ordering::Relaxed);
+        self.soloed.store(0, Ordering::Relaxed);
+        for gain in &self.gains {
+            gain.store(1.0f32.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    // What the channel gets mixed with, 0 when it is muted or other channels are soloed
+    pub fn effective_gain(&self, channel: usize) -> f32 {
+        let soloed = self.soloed.load(Ordering::Relaxed);
+        if self.is_muted(channel) || (soloed != 0 && soloed & bit(channel) == 0) {
+            return 0.0;
+        }
+        self.gain(channel)
+    }
+}
+
+// Transpose, pitch and tempo of the whole song, shared the same way as ChannelControls
+pub struct PlaybackControls {
+    transpose: AtomicI8,     // Semitones
+    pitch_factor: AtomicU32, // f32 bits
+    tempo_factor: AtomicU32, // f32 bits
+}
+
+impl Default for PlaybackControls {
+    fn default() -> Self {
+        PlaybackControls {
+            transpose: AtomicI8::new(0),
+            pitch_factor: AtomicU32::new(1.0f32.to_bits()),
+            tempo_factor: AtomicU32::new(1.0f32.to_bits()),
+        }
+    }
+}
+
+impl PlaybackControls {
+    // Applies to notes played from now on
+    pub fn set_transpose(&self, semitones: i8) {
+        self.transpose.store(semitones, Ordering::Relaxed);
+    }
+
+    pub fn transpose(&self) -> i8 {
+        self.transpose.load(Ordering::Relaxed)
+    }
+
+    // Multiplies the frequency of every voice, 2 is an octave up
+    pub fn set_pitch_factor(&self, factor: f32) {
+        self.pitch_factor
+            .store(factor.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn pitch_factor(&self) -> f32 {
+        f32::from_bits(self.pitch_factor.load(Ordering::Relaxed))
+    }
+
+    // Multiplies the tempo from the next tick on, 2 plays twice as fast
+    pub fn set_tempo_factor(&self, factor: f32) {
+        self.tempo_factor
+            .store(factor.max(0.01).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn tempo_factor(&self) -> f32 {
+        f32::from_bits(self.tempo_factor.load(Ordering::Relaxed))
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    cell::UnsafeCell,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Position {
+        position: u8,
+        pattern: u8,
+    },
+    Row {
+        position: u8,
+        pattern: u8,
+        row: u16,
+    },
+    NoteOn {
+        channel: u8,
+        note: u8,
+        instrument: u8,
+    }, // Instrument 0 if the row has none
+    NoteOff {
+        channel: u8,
+    }, // Note off, cut and fade
+    Tempo(u8),
+    Speed(u8),
+    EndOfSong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub frame: u64, // Output frames since playback started, when the event is heard
+    pub kind: EventKind,
+}
+
+// Single producer, single consumer ring buffer, one slot always stays empty
+struct Queue {
+    slots: Box<[UnsafeCell<Event>]>,
+    head: AtomicUsize, // Next slot to read, only moved by the receiver
+    tail: AtomicUsize, // Next slot to write, only moved by the sender
+}
+
+// Slots are only written by the sender before publishing them and read by the receiver after
+unsafe impl Sync for Queue {}
+
+pub(crate) struct EventSender(Arc<Queue>);
+pub struct EventReceiver(Arc<Queue>);
+
+// Never allocates or blocks after creation, so the sender can live on the audio thread
+pub(crate) fn event_queue(capacity: usize) -> (EventSender, EventReceiver) {
+    let empty = Event {
+        frame: 0,
+        kind: EventKind::EndOfSong,
+    };
+    let queue = Arc::new(Queue {
+        slots: (0..capacity + 1).map(|_| UnsafeCell::new(empty)).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+
+    (EventSender(queue.clone()), EventReceiver(queue))
+}
+
+impl EventSender {
+    // Returns false if the queue is full, the event is dropped then
+    pub(crate) fn push(&mut self, event: Event) -> bool {
+        let queue = &self.0;
+        let tail = queue.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % queue.slots.len();
+        if next == queue.head.load(Ordering::Acquire) {
+            return false;
+        }
+
+        unsafe { *queue.slots[tail].get() = event };
+        queue.tail.store(next, Ordering::Release);
+        true
+    }
+}
+
+impl EventReceiver {
+    pub fn pop(&mut self) -> Option<Event> {
+        let queue = &self.0;
+        let head = queue.head.load(Ordering::Relaxed);
+        if head == queue.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let event = unsafe { *queue.slots[head].get() };
+        queue
+            .head
+            .store((head + 1) % queue.slots.len(), Ordering::Release);
+        Some(event)
+    }
+
+    // Everything that has arrived so far
+    pub fn drain(&mut self) -> impl Iterator<Item = Event> + '_ {
+        std::iter::from_fn(|| self.pop())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(row: u16) -> Event {
+        Event {
+            frame: row as u64,
+            kind: EventKind::Row {
+                position: 0,
+                pattern: 0,
+                row,
+            },
+        }
+    }
+
+    #[test]
+    fn fifo_across_wraparound() {
+        let (mut sender, mut receiver) = event_queue(3);
+
+        for round in 0..5 {
+            for i in 0..2 {
+                assert!(sender.push(row(round * 2 + i)));
+            }
+            assert_eq!(receiver.pop(), Some(row(round * 2)));
+            assert_eq!(receiver.pop(), Some(row(round * 2 + 1)));
+        }
+        assert_eq!(receiver.pop(), None);
+    }
+
+    #[test]
+    fn full_queue_drops_events() {
+        let (mut sender, mut receiver) = event_queue(2);
+
+        assert!(sender.push(row(0)));
+        assert!(sender.push(row(1)));
+        assert!(!sender.push(row(2)));
+
+        assert_eq!(receiver.pop(), Some(row(0)));
+        assert!(sender.push(row(3)));
+        assert_eq!(receiver.drain().collect::<Vec<_>>(), [row(1), row(3)]);
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let (mut sender, mut receiver) = event_queue(0);
+
+        assert!(!sender.push(row(0)));
+        assert_eq!(receiver.pop(), None);
+    }
+}
@@ -1,6 +1,6 @@
 use super::module::{
-    Column, Effect, LoopType, Module, ModuleInterface, Note, Pattern, PlaybackMode, Row, Sample,
-    VolEffect,
+    Column, Effect, Envelope, EnvelopeNode, Instrument, LoopType, Module, ModuleInterface,
+    NewNoteAction, Note, Pattern, PlaybackMode, Row, Sample, TempoMode, VolEffect,
 };
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::{
@@ -16,7 +16,7 @@ pub struct ITModule {
     /*0000*/
     _impm: [u8; 4],
     pub song_name: [u8; 26],
-    /*001E*/ _pattern_highlight: u16, // Editor only, irrelevant
+    /*001E*/ pattern_highlight: u16, // Low byte: rows/beat, high byte: rows/measure
     /*0020*/ order_amount: u16,
     instrument_amount: u16,
     sample_amount: u16,
@@ -180,7 +180,7 @@ impl Default for ITColumn {
 pub type ITRow = Vec<Vec<ITColumn>>;
 
 impl ITPattern {
-    pub fn parse_packed_bytes(&mut self, pattern_bytes: &mut &[u8]) {
+    fn parse_packed_bytes(&mut self, pattern_bytes: &mut &[u8]) -> io::Result<()> {
         let mut channel_variable = 0u8; // channelvariable according to ITTECH.TXT
         let mut max_channel = 0u8;
 
@@ -198,9 +198,7 @@ impl ITPattern {
         let mut column = ITColumn::default();
 
         while self.rows.len() != self.rows_amount.into() {
-            pattern_bytes
-                .read_exact(slice::from_mut(&mut channel_variable))
-                .unwrap();
+            pattern_bytes.read_exact(slice::from_mut(&mut channel_variable))?;
             if channel_variable != 0 {
                 let channel_number = ((channel_variable - 1) & 63) as usize;
                 if (max_channel as usize) < channel_number + 1 {
@@ -208,43 +206,31 @@ impl ITPattern {
                 };
 
                 if channel_variable & 128 != 0 {
-                    pattern_bytes
-                        .read_exact(slice::from_mut(&mut masks[channel_number]))
-                        .unwrap();
+                    pattern_bytes.read_exact(slice::from_mut(&mut masks[channel_number]))?;
                 }
 
                 if masks[channel_number] & 1 != 0 {
                     // Note
-                    pattern_bytes
-                        .read_exact(slice::from_mut(&mut column.note))
-                        .unwrap();
+                    pattern_bytes.read_exact(slice::from_mut(&mut column.note))?;
                     last_notes[channel_number] = column.note
                 }
 
                 if masks[channel_number] & 2 != 0 {
                     // Instrument
-                    pattern_bytes
-                        .read_exact(slice::from_mut(&mut column.instrument))
-                        .unwrap();
+                    pattern_bytes.read_exact(slice::from_mut(&mut column.instrument))?;
                     last_instruments[channel_number] = column.instrument
                 }
 
                 if masks[channel_number] & 4 != 0 {
                     // Volume column
-                    pattern_bytes
-                        .read_exact(slice::from_mut(&mut column.vol))
-                        .unwrap();
+                    pattern_bytes.read_exact(slice::from_mut(&mut column.vol))?;
                     last_volumes[channel_number] = column.vol
                 }
 
                 if masks[channel_number] & 8 != 0 {
                     // Command/Effect column
-                    pattern_bytes
-                        .read_exact(slice::from_mut(&mut column.effect))
-                        .unwrap();
-                    pattern_bytes
-                        .read_exact(slice::from_mut(&mut column.effect_value))
-                        .unwrap();
+                    pattern_bytes.read_exact(slice::from_mut(&mut column.effect))?;
+                    pattern_bytes.read_exact(slice::from_mut(&mut column.effect_value))?;
                     last_fx[channel_number] = column.effect;
                     last_fxvalues[channel_number] = column.effect_value;
                 }
@@ -286,6 +272,29 @@ impl ITPattern {
             }
         }
         // println!("PATTERN END");
+        Ok(())
+    }
+}
+
+impl ITEnvelope {
+    fn envelope(&self) -> Envelope {
+        Envelope {
+            enabled: self.flag & 0b1 != 0,       // Bit 0: Envelope on/off
+            loop_enabled: self.flag & 0b10 != 0, // Bit 1: Loop on/off
+            loop_start: self.loop_begin,
+            loop_end: self.loop_end,
+            sustain_enabled: self.flag & 0b100 != 0, // Bit 2: Sustain loop on/off
+            sustain_start: self.sustain_loop_begin,
+            sustain_end: self.sustain_loop_end,
+            nodes: self
+                .nodes
+                .iter()
+                .map(|n| EnvelopeNode {
+                    tick: n.tick,
+                    value: n.y as i8, // Signed for panning and pitch envelopes
+                })
+                .collect(),
+        }
     }
 }
 
@@ -296,7 +305,7 @@ impl Default for ITModule {
             // Header
             _impm: [0; 4],
             song_name: [0; 26],
-            _pattern_highlight: 0,
+            pattern_highlight: 0,
             order_amount: 0,
             instrument_amount: 0,
             sample_amount: 0,
@@ -329,11 +338,41 @@ impl Default for ITModule {
     }
 }
 
-pub struct NotAModuleError;
+// Also returned for truncated files, with the read error as the source
+#[derive(Debug)]
+pub struct NotAModuleError {
+    source: Option<io::Error>,
+}
+
 impl std::fmt::Display for NotAModuleError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "the file is not a valid IT module")
+        match &self.source {
+            Some(e) => write!(f, "the file is not a valid IT module: {}", e),
+            None => write!(f, "the file is not a valid IT module"),
+        }
+    }
+}
+
+impl std::error::Error for NotAModuleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_ref().map(|e| e as _)
+    }
+}
+
+impl From<io::Error> for NotAModuleError {
+    fn from(e: io::Error) -> Self {
+        NotAModuleError { source: Some(e) }
+    }
+}
+
+// Reads as much as is there first, so a bogus length in a truncated file can't allocate gigabytes
+fn read_sample_data(reader: &mut impl io::Read, length: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(length).read_to_end(&mut data)?;
+    if (data.len() as u64) < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
     }
+    Ok(data)
 }
 
 impl ITModule {
@@ -344,104 +383,100 @@ impl ITModule {
 
         // --- HEADER START ---
         // 0000
-        reader.read_exact(&mut module._impm).unwrap();
-        match std::str::from_utf8(&module._impm).unwrap() {
-            "IMPM" => {}
-            _ => return Err(NotAModuleError),
-        };
-        reader.read_exact(&mut module.song_name).unwrap();
+        reader.read_exact(&mut module._impm)?;
+        if &module._impm != b"IMPM" {
+            return Err(NotAModuleError { source: None });
+        }
+        reader.read_exact(&mut module.song_name)?;
 
         // 0010
         let mut philigt_buf = [0u8; 2];
-        reader.read_exact(&mut philigt_buf).unwrap();
-        module._pattern_highlight = u16::from_le_bytes(philigt_buf);
+        reader.read_exact(&mut philigt_buf)?;
+        module.pattern_highlight = u16::from_le_bytes(philigt_buf);
 
         // 0020
         let mut ordnum_buf = [0u8; 2];
-        reader.read_exact(&mut ordnum_buf).unwrap();
+        reader.read_exact(&mut ordnum_buf)?;
         module.order_amount = u16::from_le_bytes(ordnum_buf);
         let mut insnum_buf = [0u8; 2];
-        reader.read_exact(&mut insnum_buf).unwrap();
+        reader.read_exact(&mut insnum_buf)?;
         module.instrument_amount = u16::from_le_bytes(insnum_buf);
         let mut smpnum_buf = [0u8; 2];
-        reader.read_exact(&mut smpnum_buf).unwrap();
+        reader.read_exact(&mut smpnum_buf)?;
         module.sample_amount = u16::from_le_bytes(smpnum_buf);
         let mut ptnnum_buf = [0u8; 2];
-        reader.read_exact(&mut ptnnum_buf).unwrap();
+        reader.read_exact(&mut ptnnum_buf)?;
         module.pattern_amount = u16::from_le_bytes(ptnnum_buf);
         let mut trackerid_buf = [0u8; 2];
-        reader.read_exact(&mut trackerid_buf).unwrap();
+        reader.read_exact(&mut trackerid_buf)?;
         module.tracker_id = u16::from_le_bytes(trackerid_buf);
         let mut formatid_buf = [0u8; 2];
-        reader.read_exact(&mut formatid_buf).unwrap();
+        reader.read_exact(&mut formatid_buf)?;
         module.format_id = u16::from_le_bytes(formatid_buf);
         let mut flags_buf = [0u8; 2];
-        reader.read_exact(&mut flags_buf).unwrap();
+        reader.read_exact(&mut flags_buf)?;
         module.flags = u16::from_le_bytes(flags_buf);
         let mut special_buf = [0u8; 2];
-        reader.read_exact(&mut special_buf).unwrap();
+        reader.read_exact(&mut special_buf)?;
         module.special = u16::from_le_bytes(special_buf);
 
         // 0030
         let mut gv_buf = [0u8];
-        reader.read_exact(&mut gv_buf).unwrap();
+        reader.read_exact(&mut gv_buf)?;
         module.global_volume = gv_buf[0];
         let mut mv_buf = [0u8];
-        reader.read_exact(&mut mv_buf).unwrap();
+        reader.read_exact(&mut mv_buf)?;
         module.mixing_volume = mv_buf[0];
         let mut is_buf = [0u8];
-        reader.read_exact(&mut is_buf).unwrap();
+        reader.read_exact(&mut is_buf)?;
         module.initial_speed = is_buf[0];
         let mut it_buf = [0u8];
-        reader.read_exact(&mut it_buf).unwrap();
+        reader.read_exact(&mut it_buf)?;
         module.initial_tempo = it_buf[0];
         let mut sep_buf = [0u8];
-        reader.read_exact(&mut sep_buf).unwrap();
+        reader.read_exact(&mut sep_buf)?;
         module.separation = sep_buf[0];
         let mut pwd_buf = [0u8];
-        reader.read_exact(&mut pwd_buf).unwrap();
+        reader.read_exact(&mut pwd_buf)?;
         module.pitch_wheel_depth = pwd_buf[0];
         let mut msglgth_buf = [0u8; 2];
-        reader.read_exact(&mut msglgth_buf).unwrap();
+        reader.read_exact(&mut msglgth_buf)?;
         module.message_length = u16::from_le_bytes(msglgth_buf);
         let mut msgoffset_buf = [0u8; 4];
-        reader.read_exact(&mut msgoffset_buf).unwrap();
+        reader.read_exact(&mut msgoffset_buf)?;
         module.message_offset = u32::from_le_bytes(msgoffset_buf);
-        reader.read_exact(&mut module._reserved).unwrap();
+        reader.read_exact(&mut module._reserved)?;
 
         // 0040
-        reader.read_exact(&mut module.channel_pan).unwrap();
+        reader.read_exact(&mut module.channel_pan)?;
 
         // 0080
-        reader.read_exact(&mut module.channel_volume).unwrap();
+        reader.read_exact(&mut module.channel_volume)?;
 
         // 00C0
         module.orders.resize(module.order_amount as usize, 0);
-        reader.read_exact(&mut module.orders).unwrap();
+        reader.read_exact(&mut module.orders)?;
 
         // xxxx (Offsets)
         // Instruments
-        let mut io_buf = Vec::<u8>::with_capacity((module.instrument_amount * 4) as usize);
-        io_buf.resize((module.instrument_amount * 4) as usize, 0);
-        reader.read_exact(&mut io_buf).unwrap();
+        let mut io_buf = vec![0u8; module.instrument_amount as usize * 4];
+        reader.read_exact(&mut io_buf)?;
         module.instrument_offsets = io_buf
             .chunks(4)
             .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
             .collect::<Vec<u32>>();
 
         // Samples
-        let mut so_buf = Vec::<u8>::with_capacity((module.sample_amount * 4) as usize);
-        so_buf.resize((module.sample_amount * 4) as usize, 0);
-        reader.read_exact(&mut so_buf).unwrap();
+        let mut so_buf = vec![0u8; module.sample_amount as usize * 4];
+        reader.read_exact(&mut so_buf)?;
         module.sample_offsets = so_buf
             .chunks(4)
             .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
             .collect::<Vec<u32>>();
 
         // Patterns
-        let mut po_buf = Vec::<u8>::with_capacity((module.pattern_amount * 4) as usize);
-        po_buf.resize((module.pattern_amount * 4) as usize, 0);
-        reader.read_exact(&mut po_buf).unwrap();
+        let mut po_buf = vec![0u8; module.pattern_amount as usize * 4];
+        reader.read_exact(&mut po_buf)?;
         module.pattern_offsets = po_buf
             .chunks(4)
             .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
@@ -450,46 +485,46 @@ impl ITModule {
 
         // --- INSTRUMENTS START ---
         for offset in &module.instrument_offsets {
-            reader.seek(SeekFrom::Start(*offset as u64)).unwrap();
+            reader.seek(SeekFrom::Start(*offset as u64))?;
             let mut instrument = ITInstrument::default();
 
             // 0000
-            reader.read_exact(&mut instrument._impi).unwrap();
-            reader.read_exact(&mut instrument.filename).unwrap();
+            reader.read_exact(&mut instrument._impi)?;
+            reader.read_exact(&mut instrument.filename)?;
 
             // 0010
-            instrument._00h = reader.read_u8().unwrap();
-            instrument.new_note_action = reader.read_u8().unwrap();
-            instrument.duplicate_check_type = reader.read_u8().unwrap();
-            instrument.duplicate_check_action = reader.read_u8().unwrap();
-            instrument.fadeout = reader.read_u16::<LittleEndian>().unwrap();
-            instrument.pitch_pan_sepraration = reader.read_i8().unwrap();
-            instrument.pitch_pan_center = reader.read_u8().unwrap();
-            instrument.global_volume = reader.read_u8().unwrap();
-            instrument.default_pan = reader.read_u8().unwrap();
-            instrument.random_volume = reader.read_u8().unwrap();
-            instrument.random_pan = reader.read_u8().unwrap();
-            instrument._tracker_version = reader.read_u16::<LittleEndian>().unwrap();
-            instrument._number_of_samples = reader.read_u8().unwrap();
-            instrument._x = reader.read_u8().unwrap();
+            instrument._00h = reader.read_u8()?;
+            instrument.new_note_action = reader.read_u8()?;
+            instrument.duplicate_check_type = reader.read_u8()?;
+            instrument.duplicate_check_action = reader.read_u8()?;
+            instrument.fadeout = reader.read_u16::<LittleEndian>()?;
+            instrument.pitch_pan_sepraration = reader.read_i8()?;
+            instrument.pitch_pan_center = reader.read_u8()?;
+            instrument.global_volume = reader.read_u8()?;
+            instrument.default_pan = reader.read_u8()?;
+            instrument.random_volume = reader.read_u8()?;
+            instrument.random_pan = reader.read_u8()?;
+            instrument._tracker_version = reader.read_u16::<LittleEndian>()?;
+            instrument._number_of_samples = reader.read_u8()?;
+            instrument._x = reader.read_u8()?;
 
             // 0020
-            reader.read_exact(&mut instrument.instrument_name).unwrap();
+            reader.read_exact(&mut instrument.instrument_name)?;
 
             // 0030
-            instrument.initial_filter_cutoff = reader.read_u8().unwrap();
-            instrument.initial_filter_resonance = reader.read_u8().unwrap();
-            instrument.midi_channel = reader.read_u8().unwrap();
-            instrument.midi_program = reader.read_u8().unwrap();
-            instrument.midi_bank = reader.read_u16::<LittleEndian>().unwrap();
+            instrument.initial_filter_cutoff = reader.read_u8()?;
+            instrument.initial_filter_resonance = reader.read_u8()?;
+            instrument.midi_channel = reader.read_u8()?;
+            instrument.midi_program = reader.read_u8()?;
+            instrument.midi_bank = reader.read_u16::<LittleEndian>()?;
 
             // 0040
             for _ in 0..120 {
                 // 240 bytes
                 let mut pair = ITNoteSamplePair::default();
 
-                pair.note = reader.read_u8().unwrap();
-                pair.sample = reader.read_u8().unwrap();
+                pair.note = reader.read_u8()?;
+                pair.sample = reader.read_u8()?;
                 instrument.note_sample_table.push(pair);
             }
 
@@ -497,20 +532,24 @@ impl ITModule {
             for i in 0..3 as usize {
                 let mut env = ITEnvelope::default();
 
-                env.flag = reader.read_u8().unwrap();
-                env.node_amount = reader.read_u8().unwrap();
-                env.loop_begin = reader.read_u8().unwrap();
-                env.loop_end = reader.read_u8().unwrap();
-                env.sustain_loop_begin = reader.read_u8().unwrap();
-                env.sustain_loop_end = reader.read_u8().unwrap();
+                env.flag = reader.read_u8()?;
+                env.node_amount = reader.read_u8()?;
+                env.loop_begin = reader.read_u8()?;
+                env.loop_end = reader.read_u8()?;
+                env.sustain_loop_begin = reader.read_u8()?;
+                env.sustain_loop_end = reader.read_u8()?;
 
-                for _ in 0..env.node_amount {
+                // There's always room for 25 nodes, followed by a reserved byte
+                for n in 0..25 {
                     let mut node = ITEnvelopeNode::default();
 
-                    node.y = reader.read_u8().unwrap();
-                    node.tick = reader.read_u16::<LittleEndian>().unwrap();
-                    env.nodes.push(node);
+                    node.y = reader.read_u8()?;
+                    node.tick = reader.read_u16::<LittleEndian>()?;
+                    if n < env.node_amount {
+                        env.nodes.push(node);
+                    }
                 }
+                reader.read_u8()?;
 
                 instrument.envelopes[i] = env;
             }
@@ -521,51 +560,47 @@ impl ITModule {
 
         // --- SAMPLES START ---
         for offset in module.sample_offsets.as_slice() {
-            reader.seek(SeekFrom::Start(*offset as u64)).unwrap();
+            reader.seek(SeekFrom::Start(*offset as u64))?;
             let mut sample = ITSample::default();
 
             // 0000
-            reader.read_exact(&mut sample._imps).unwrap();
-            reader.read_exact(&mut sample.filename).unwrap();
+            reader.read_exact(&mut sample._imps)?;
+            reader.read_exact(&mut sample.filename)?;
 
             // 0010
-            sample._00h = reader.read_u8().unwrap();
-            sample.global_volume = reader.read_u8().unwrap();
-            sample.flags = reader.read_u8().unwrap();
-            sample.volume = reader.read_u8().unwrap();
+            sample._00h = reader.read_u8()?;
+            sample.global_volume = reader.read_u8()?;
+            sample.flags = reader.read_u8()?;
+            sample.volume = reader.read_u8()?;
 
-            reader.read_exact(&mut sample.sample_name).unwrap();
+            reader.read_exact(&mut sample.sample_name)?;
 
             // 0020
-            sample.convert = reader.read_u8().unwrap();
-            sample.default_pan = reader.read_u8().unwrap();
+            sample.convert = reader.read_u8()?;
+            sample.default_pan = reader.read_u8()?;
 
             // 0030
-            sample.length = reader.read_u32::<LittleEndian>().unwrap();
-            sample.loop_begin = reader.read_u32::<LittleEndian>().unwrap();
-            sample.loop_end = reader.read_u32::<LittleEndian>().unwrap();
-            sample.c5_speed = reader.read_u32::<LittleEndian>().unwrap();
+            sample.length = reader.read_u32::<LittleEndian>()?;
+            sample.loop_begin = reader.read_u32::<LittleEndian>()?;
+            sample.loop_end = reader.read_u32::<LittleEndian>()?;
+            sample.c5_speed = reader.read_u32::<LittleEndian>()?;
 
             // 0040
-            sample.sustain_loop_begin = reader.read_u32::<LittleEndian>().unwrap();
-            sample.sustain_loop_end = reader.read_u32::<LittleEndian>().unwrap();
-            sample.sample_pointer = reader.read_u32::<LittleEndian>().unwrap();
+            sample.sustain_loop_begin = reader.read_u32::<LittleEndian>()?;
+            sample.sustain_loop_end = reader.read_u32::<LittleEndian>()?;
+            sample.sample_pointer = reader.read_u32::<LittleEndian>()?;
 
-            sample.vibrato_speed = reader.read_u8().unwrap();
-            sample.vibrato_depth = reader.read_u8().unwrap();
-            sample.vibrato_rate = reader.read_u8().unwrap();
-            sample.vibrato_type = reader.read_u8().unwrap();
+            sample.vibrato_speed = reader.read_u8()?;
+            sample.vibrato_depth = reader.read_u8()?;
+            sample.vibrato_rate = reader.read_u8()?;
+            sample.vibrato_type = reader.read_u8()?;
 
             // Data
-            reader
-                .seek(SeekFrom::Start(sample.sample_pointer as u64))
-                .unwrap();
+            reader.seek(SeekFrom::Start(sample.sample_pointer as u64))?;
 
             if sample.flags & 0b10 != 0 {
                 // Sample is 16 bit
-                let mut data: Vec<u8> = Vec::with_capacity(sample.length as usize * 2);
-                data.resize((sample.length * 2).try_into().unwrap(), 0);
-                reader.read_exact(&mut data).unwrap();
+                let data = read_sample_data(&mut reader, sample.length as u64 * 2)?;
 
                 if sample.convert & 0b1 != 0 {
                     // Signed?
@@ -581,9 +616,7 @@ impl ITModule {
                 }
             } else {
                 // Sample is 8 bit
-                let mut data: Vec<u8> = Vec::with_capacity(sample.length as usize);
-                data.resize((sample.length).try_into().unwrap(), 0);
-                reader.read_exact(&mut data).unwrap();
+                let data = read_sample_data(&mut reader, sample.length as u64)?;
 
                 if sample.convert & 0b1 != 0 {
                     // Signed?
@@ -608,19 +641,19 @@ impl ITModule {
             }
 
             // println!("Offset: {}", offset);
-            reader.seek(SeekFrom::Start(*offset as u64)).unwrap();
+            reader.seek(SeekFrom::Start(*offset as u64))?;
             let mut pattern = ITPattern::default();
 
-            pattern.length = reader.read_u16::<LittleEndian>().unwrap();
-            pattern.rows_amount = reader.read_u16::<LittleEndian>().unwrap();
+            pattern.length = reader.read_u16::<LittleEndian>()?;
+            pattern.rows_amount = reader.read_u16::<LittleEndian>()?;
             // println!("Rows: {}", pattern.rows_amount);
-            reader.read_exact(&mut pattern._x).unwrap(); // skip padding(?)
+            reader.read_exact(&mut pattern._x)?; // skip padding(?)
 
             let mut pattern_bytes = Vec::<u8>::with_capacity(pattern.length.into());
             pattern_bytes.resize(pattern.length.into(), 0);
-            reader.read_exact(&mut pattern_bytes).unwrap();
+            reader.read_exact(&mut pattern_bytes)?;
 
-            pattern.parse_packed_bytes(&mut pattern_bytes.as_slice());
+            pattern.parse_packed_bytes(&mut pattern_bytes.as_slice())?;
 
             module.patterns.push(pattern);
         }
@@ -644,14 +677,64 @@ impl ModuleInterface for ITModule {
                 loop_start: s.loop_begin,
                 loop_end: s.loop_end,
 
+                sustain_loop_type: match s.flags & 0b10100000 {
+                    32 => LoopType::Forward,
+                    160 => LoopType::PingPong,
+                    _ => LoopType::None,
+                },
+                sustain_loop_start: s.sustain_loop_begin,
+                sustain_loop_end: s.sustain_loop_end,
+
                 default_volume: s.volume,
                 global_volume: s.global_volume,
+                default_pan: match s.default_pan & 128 {
+                    // Bit 7: On = Use default pan
+                    0 => None,
+                    _ => Some((s.default_pan & 127).min(64)),
+                },
 
                 audio: s.audio.clone(),
             })
             .collect()
     }
 
+    fn instruments(&self) -> Vec<Instrument> {
+        self.instruments
+            .iter()
+            .map(|i| Instrument {
+                new_note_action: match i.new_note_action {
+                    1 => NewNoteAction::Continue,
+                    2 => NewNoteAction::NoteOff,
+                    3 => NewNoteAction::NoteFade,
+                    _ => NewNoteAction::Cut,
+                },
+                fadeout: i.fadeout,
+
+                global_volume: i.global_volume,
+                default_pan: match i.default_pan & 128 {
+                    // Bit 7: On = Don't use default pan
+                    0 => Some(i.default_pan.min(64)),
+                    _ => None,
+                },
+                random_volume: i.random_volume.min(100),
+                random_pan: i.random_pan.min(64),
+
+                pitch_pan_separation: i.pitch_pan_sepraration.clamp(-32, 32),
+                pitch_pan_center: i.pitch_pan_center,
+
+                note_sample_table: i
+                    .note_sample_table
+                    .iter()
+                    .map(|pair| (pair.note, pair.sample))
+                    .collect(),
+
+                volume_envelope: i.envelopes[0].envelope(),
+                pan_envelope: i.envelopes[1].envelope(),
+                pitch_envelope: i.envelopes[2].envelope(),
+            })
+            .collect()
+    }
+
     fn patterns(&self) -> Vec<Pattern> {
         let mut patterns = Vec::<Pattern>::with_capacity(self.patterns.len());
 
@@ -703,7 +786,7 @@ impl ModuleInterface for ITModule {
                             18 => Effect::Tremolo(c.effect_value),
                             19 => match c.effect_value & 0xF0 {
                                 // Sxy
-                                0x10 => Effect::GlissandoControl(c.effect_value & 0x0F > 1),
+                                0x10 => Effect::GlissandoControl(c.effect_value & 0x0F != 0),
                                 0x20 => Effect::SetFinetune(c.effect_value & 0x0F),
                                 0x30 => Effect::SetVibratoWaveform(c.effect_value & 0x0F),
                                 0x40 => Effect::SetTremoloWaveform(c.effect_value & 0x0F),
@@ -741,8 +824,8 @@ impl ModuleInterface for ITModule {
                                 _ => Effect::None,
                             },
                             20 => match c.effect_value & 0xF0 {
-                                0x0 => Effect::DecTempo(c.effect_value & 0x0F),
-                                0x1 => Effect::IncTempo(c.effect_value & 0x0F),
+                                0x00 => Effect::DecTempo(c.effect_value & 0x0F),
+                                0x10 => Effect::IncTempo(c.effect_value & 0x0F),
                                 _ => Effect::SetTempo(c.effect_value),
                             },
                             21 => Effect::FineVibrato(c.effect_value),
@@ -775,9 +858,29 @@ impl ModuleInterface for ITModule {
                 PlaybackMode::ITSample
             },
             linear_freq_slides: self.flags & 0b1000 != 0, // Bit 3: On = Linear slides, Off = Amiga slides.
+            old_effects: self.flags & 0b10000 != 0, // Bit 4: On = Old Effects, Off = IT Effects.
+            compatible_gxx: self.flags & 0b100000 != 0, // Bit 5: On = Link Gxx with Exx/Fxx memory off.
             initial_tempo: self.initial_tempo,
             initial_speed: self.initial_speed,
+            tempo_mode: TempoMode::Classic, // IT has no notion of tempo modes
+            rows_per_beat: match self.pattern_highlight & 0xFF {
+                // Low byte of the pattern highlight is rows per beat
+                0 => 4,
+                rpb => rpb as u8,
+            },
+            mixing_volume: self.mixing_volume.min(128),
+            channel_pan: self
+                .channel_pan
+                .iter()
+                .map(|p| match p & 127 {
+                    100 => 32, // Surround, play it centered
+                    pan => pan.min(64),
+                })
+                .collect(),
+            // Bit 7 of the pan disables the channel
+            channel_enabled: self.channel_pan.iter().map(|p| p & 128 == 0).collect(),
             samples: self.samples(),
+            instruments: self.instruments(),
             patterns: self.patterns(),
             playlist: self.orders.clone(),
             name: String::from_utf8_lossy(&self.song_name)
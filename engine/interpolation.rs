@@ -0,0 +1,151 @@
+use std::{f64::consts::PI, sync::OnceLock};
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Interpolation {
+    #[default]
+    None,
+    Linear,
+    Cubic, // Catmull-Rom
+    Sinc8,
+    Sinc16,
+    Sinc32,
+}
+
+// Fractional positions the sinc tables are computed for, in between them the coefficients are lerped
+const PHASES: usize = 256;
+
+impl Interpolation {
+    // Number of neighbouring samples needed, tap (taps - 1) / 2 is the one at or before the position
+    pub fn taps(self) -> usize {
+        match self {
+            Interpolation::None => 1,
+            Interpolation::Linear => 2,
+            Interpolation::Cubic => 4,
+            Interpolation::Sinc8 => 8,
+            Interpolation::Sinc16 => 16,
+            Interpolation::Sinc32 => 32,
+        }
+    }
+
+    pub fn interpolate(self, taps: &[f32], fraction: f32) -> f32 {
+        match self {
+            Interpolation::None => taps[0],
+            Interpolation::Linear => linear(taps, fraction),
+            Interpolation::Cubic => cubic(taps, fraction),
+            _ => sinc_table(self.taps()).interpolate(taps, fraction),
+        }
+    }
+
+    // Builds the lookup tables up front, so the audio thread doesn't have to
+    pub fn prepare() {
+        for taps in [8, 16, 32] {
+            sinc_table(taps);
+        }
+    }
+}
+
+pub(crate) fn linear(taps: &[f32], fraction: f32) -> f32 {
+    taps[0] + (taps[1] - taps[0]) * fraction
+}
+
+pub(crate) fn cubic(taps: &[f32], fraction: f32) -> f32 {
+    let (y0, y1, y2, y3) = (taps[0], taps[1], taps[2], taps[3]);
+
+    let a = -0.5 * y0 + 1.5 * y1 - 1.5 * y2 + 0.5 * y3;
+    let b = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+    let c = -0.5 * y0 + 0.5 * y2;
+
+    ((a * fraction + b) * fraction + c) * fraction + y1
+}
+
+pub(crate) struct SincTable {
+    taps: usize,
+    coefficients: Vec<f32>, // PHASES + 1 rows of taps
+    deltas: Vec<f32>,       // Difference to the next row
+}
+
+impl SincTable {
+    fn new(taps: usize, cutoff: f64, beta: f64) -> SincTable {
+        let half = taps as f64 / 2.0;
+        let center = (taps - 1) / 2;
+        let mut coefficients = Vec::with_capacity((PHASES + 1) * taps);
+
+        for phase in 0..=PHASES {
+            let fraction = phase as f64 / PHASES as f64;
+            let row: Vec<f64> = (0..taps)
+                .map(|i| {
+                    let x = i as f64 - center as f64 - fraction;
+                    let window = bessel_i0(beta * (1.0 - (x / half).powi(2)).max(0.0).sqrt())
+                        / bessel_i0(beta);
+                    sinc(x * cutoff) * cutoff * window
+                })
+                .collect();
+
+            // Unity gain for DC on every phase
+            let sum: f64 = row.iter().sum();
+            coefficients.extend(row.iter().map(|c| (c / sum) as f32));
+        }
+
+        let deltas = (0..PHASES * taps)
+            .map(|i| coefficients[i + taps] - coefficients[i])
+            .collect();
+
+        SincTable {
+            taps,
+            coefficients,
+            deltas,
+        }
+    }
+
+    pub(crate) fn interpolate(&self, taps: &[f32], fraction: f32) -> f32 {
+        let position = fraction * PHASES as f32;
+        let phase = (position as usize).min(PHASES - 1);
+        let t = position - phase as f32;
+
+        let row = phase * self.taps;
+        let coefficients = &self.coefficients[row..row + self.taps];
+        let deltas = &self.deltas[row..row + self.taps];
+
+        taps.iter()
+            .zip(coefficients.iter().zip(deltas))
+            .map(|(x, (c, d))| x * (c + d * t))
+            .sum()
+    }
+}
+
+pub(crate) fn sinc_table(taps: usize) -> &'static SincTable {
+    static SINC8: OnceLock<SincTable> = OnceLock::new();
+    static SINC16: OnceLock<SincTable> = OnceLock::new();
+    static SINC32: OnceLock<SincTable> = OnceLock::new();
+
+    // Shorter kernels roll off earlier to keep the imaging down
+    match taps {
+        8 => SINC8.get_or_init(|| SincTable::new(8, 0.88, 5.0)),
+        16 => SINC16.get_or_init(|| SincTable::new(16, 0.94, 7.0)),
+        32 => SINC32.get_or_init(|| SincTable::new(32, 0.97, 9.0)),
+        _ => panic!("no sinc table with {taps} taps"),
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        return 1.0;
+    }
+    (x * PI).sin() / (x * PI)
+}
+
+// Zeroth order modified Bessel function of the first kind, for the Kaiser window
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)).powi(2);
+        sum += term;
+        k += 1.0;
+    }
+
+    sum
+}
@@ -1,3 +1,7 @@
+pub mod controls;
+pub mod events;
 pub mod format_it;
+pub mod interpolation;
 pub mod module;
 pub mod player;
+pub mod realtime;
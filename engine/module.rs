@@ -96,13 +96,32 @@ pub enum VolEffect {
     Volume(u8),           // vxx
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub enum LoopType {
     None,
     Forward,
     PingPong,
 }
 
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TempoMode {
+    // How a tempo value translates into tick length
+    #[default]
+    Classic, // 2.5 / tempo seconds per tick, like IT
+    Alternative, // 1 / tempo seconds per tick
+    Modern,      // Tempo is BPM, tick length depends on speed and rows per beat (OpenMPT)
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub enum NewNoteAction {
+    #[default]
+    Cut,
+    Continue,
+    NoteOff,
+    NoteFade,
+}
+
 #[derive(Debug)]
 pub enum PlaybackMode {
     MOD,
@@ -119,14 +138,59 @@ pub struct Sample {
     pub loop_start: u32,
     pub loop_end: u32,
 
+    pub sustain_loop_type: LoopType,
+    pub sustain_loop_start: u32,
+    pub sustain_loop_end: u32,
+
     pub default_volume: u8,
     pub global_volume: u8,
-    // TODO: sustain loops
+    pub default_pan: Option<u8>, // 0..64
 
     // TODO: vibrato
     pub audio: Vec<i16>,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct EnvelopeNode {
+    pub tick: u16,
+    pub value: i8, // 0..64 for volume, -32..32 for panning and pitch
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Envelope {
+    pub enabled: bool,
+
+    pub loop_enabled: bool,
+    pub loop_start: u8, // Node indices
+    pub loop_end: u8,
+
+    pub sustain_enabled: bool,
+    pub sustain_start: u8,
+    pub sustain_end: u8,
+
+    pub nodes: Vec<EnvelopeNode>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Instrument {
+    pub new_note_action: NewNoteAction,
+    pub fadeout: u16, // Subtracted from a fade volume of 1024 every tick
+
+    pub global_volume: u8,       // 0..128
+    pub default_pan: Option<u8>, // 0..64
+    pub random_volume: u8,       // Swing in percent of the note volume
+    pub random_pan: u8,          // Swing in panning units
+
+    pub pitch_pan_separation: i8, // -32..32, panning shift per 8 notes from the center
+    pub pitch_pan_center: u8,     // Note
+
+    pub note_sample_table: Vec<(u8, u8)>, // Note to (note, sample) mapping, sample 0 means none
+
+    pub volume_envelope: Envelope,
+    pub pan_envelope: Envelope,
+    pub pitch_envelope: Envelope,
+}
+
 pub type Pattern = Vec<Row>;
 pub type Row = Vec<Column>;
 
@@ -144,18 +208,41 @@ pub struct Module {
     pub mode: PlaybackMode,
 
     pub linear_freq_slides: bool,
+    pub old_effects: bool,
+    pub compatible_gxx: bool,
     pub initial_tempo: u8,
     pub initial_speed: u8,
+    pub tempo_mode: TempoMode,
+    pub rows_per_beat: u8, // Only used by TempoMode::Modern
+    pub mixing_volume: u8, // 0..128
+
+    pub channel_pan: Vec<u8>,       // 0..64
+    pub channel_enabled: Vec<bool>, // Disabled channels are silent
 
     pub samples: Vec<Sample>,
-    //TODO: instruments
+    pub instruments: Vec<Instrument>,
     pub patterns: Vec<Pattern>,
     pub playlist: Vec<u8>,
 }
 
+impl Module {
+    // Enabled channels that play at least one note somewhere in the song
+    pub fn used_channels(&self) -> Vec<usize> {
+        (0..64)
+            .filter(|&c| self.channel_enabled.get(c).copied().unwrap_or(true))
+            .filter(|&c| {
+                self.patterns.iter().flatten().any(|row| {
+                    row.get(c)
+                        .is_some_and(|column| !matches!(column.note, Note::None))
+                })
+            })
+            .collect()
+    }
+}
+
 pub trait ModuleInterface {
     fn samples(&self) -> Vec<Sample>;
-    // TODO: instruments
+    fn instruments(&self) -> Vec<Instrument>;
     fn patterns(&self) -> Vec<Pattern>;
 
     fn module(&self) -> Module;
@@ -1,69 +1,186 @@
 use std::{
     array,
+    collections::HashMap,
     f32::consts::PI,
-    io::{stdout, Write},
+    ops::Range,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::engine::module::Effect;
 
-use super::module::{LoopType, Module, Note, VolEffect};
-use sdl2::audio::AudioCallback;
+use super::controls::{ChannelControls, PlaybackControls};
+use super::events::{event_queue, Event, EventKind, EventReceiver, EventSender};
+use super::interpolation::{self, Interpolation};
+use super::module::{
+    Envelope, Instrument, LoopType, Module, NewNoteAction, Note, PlaybackMode, Sample, TempoMode,
+    VolEffect,
+};
+use super::realtime::RenderGuard;
+
+#[derive(Default, Debug, Clone, Copy)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OutOfRangeOffset {
+    // What Oxx does when it points past the end of the sample
+    #[default]
+    Ignore, // IT: play the sample from the start
+    LoopStart, // OpenMPT: jump to the loop start, cut the note if the sample doesn't loop
+    Stop,      // IT with Old Effects: seek to the end, which stops the note
+}
 
-#[derive(Default, Debug, Clone, Copy, clap::ValueEnum)]
-pub enum Interpolation {
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum EndOfSong {
+    // What happens once the song reaches its end or jumps back
     #[default]
-    None,
-    Linear,
-    Sinc16,
-    Sinc32,
-    // Sinc64
+    Stop,
+    Loop(u32), // Repeat this many times, then stop
+    LoopForever,
+    FadeOut(f32), // Keep playing, fading out over this many seconds
+}
+
+// Voices left behind by New Note Actions, on top of the 64 pattern channels
+const BACKGROUND_VOICES: usize = 192;
+
+// Frames mixed at once, render calls with larger buffers are split
+const MIX_BUFFER_FRAMES: usize = 1024;
+
+// xorshift64*, plenty for volume and pan swing and reproducible from a seed
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // The state must never be zero
+        Rng(if seed == 0 { 0x9E3779B97F4A7C15 } else { seed })
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    // Uniform in -max..max
+    fn swing(&mut self, max: u8) -> f32 {
+        let unit = (self.next() >> 40) as f32 / (1u64 << 24) as f32;
+        (unit * 2.0 - 1.0) * max as f32
+    }
+}
+
+#[derive(Clone, Default)]
+struct EnvelopeState {
+    enabled: bool,
+    tick: u16,
+    value: f32, // 0..64 for volume, -32..32 for panning and pitch
 }
 
 #[derive(Clone)]
 struct Channel<'a> {
     module: &'a Module,
+    host_channel: u8, // Pattern channel this voice belongs to
 
     current_sample_index: u8,
+    current_instrument: Option<u8>, // Instrument mode only
+    note: u8,                       // After the instrument's note mapping
+    new_note_action: NewNoteAction, // S73..S76 override the instrument's NNA
     playing: bool,
     freq: f32,
+    play_freq: f32, // freq with vibrato applied
     position: f64,
     backwards: bool,
-
-    porta_memory: u8,    // Exx, Fxx, Gxx
-    last_note: u8,       // Gxx
-    offset_memory: u8,   // Oxx
-    volume_memory: u8,   // Dxy
-    retrigger_ticks: u8, // Qxy
+    looped: bool, // Been around the loop, what comes before the loop start is the loop end
+
+    porta_memory: u8,      // Exx, Fxx, Gxx
+    tone_porta_memory: u8, // Gxx with Compatible Gxx
+    last_note: u8,         // Gxx
+    offset_memory: u8,     // Oxx
+    high_offset: u8,       // SAx
+    volume_memory: u8,     // Dxy
+    retrigger_ticks: u8,   // Qxy
+    tempo_memory: u8,      // T0x, T1x
+    loop_row: u16,         // SB0
+    loop_count: u8,        // SBx, repeats left
+
+    vibrato_speed: u8,    // Hxy, Uxy
+    vibrato_depth: u8,    // Hxy, Uxy, in 1/64 semitones
+    vibrato_position: u8, // 0..255 is one full cycle
+    vibrato_delta: i32,   // Pitch offset for the current tick, in 1/64 semitones
+
+    glissando: bool,        // S1x
+    snap_to_semitone: bool, // Set on rows where a glissando portamento is running
+
+    key_off: bool, // Releases sustain loops
+    fading: bool,
+    fade_volume: f32, // 0..1
+
+    volume_envelope: EnvelopeState,
+    pan_envelope: EnvelopeState,
+    pitch_envelope: EnvelopeState,
 
     volume: f32,
-    // panning: i8,
+    panning: f32,      // 0..64
+    volume_swing: f32, // Fraction of the volume, from the instrument's random volume
+    pan_swing: f32,    // From the instrument's random pan
+    pitch_pan: f32,    // From the instrument's pitch-pan separation
+
+    // Left and right gain, ramped towards the target over a few frames instead of jumping
+    gain: [f32; 2],
+    gain_target: [f32; 2],
+    gain_step: [f32; 2],
+    ramp_frames: u32,
+    stopping: bool,    // Ramping down to silence, stops playing once there
+    channel_gain: f32, // From the host's mute, solo and gain controls
+    pitch_factor: f32, // From the host's playback controls
 }
 
-fn sinc(x: f32) -> f32 {
-    if x <= 0.0001 && x >= -0.0001 {
-        return 1.0;
-    };
-    (x * PI).sin() / (x * PI)
-}
+fn envelope_value(envelope: &Envelope, tick: u16) -> f32 {
+    let nodes = &envelope.nodes;
 
-fn vec_linear(vec: &Vec<i16>, index: f32) -> i16 {
-    (vec[index.floor() as usize] as f32
-        + (index - index.floor())
-            * ((vec[index.ceil() as usize] as f32 - vec[index.floor() as usize] as f32)
-                * (index - index.floor()))) as i16
+    match nodes.iter().position(|n| n.tick > tick) {
+        Some(0) => nodes[0].value as f32,
+        Some(i) => {
+            let (a, b) = (&nodes[i - 1], &nodes[i]);
+            a.value as f32
+                + (b.value as f32 - a.value as f32) * (tick - a.tick) as f32
+                    / (b.tick - a.tick) as f32
+        }
+        None => nodes[nodes.len() - 1].value as f32,
+    }
 }
 
-fn vec_sinc(vec: &Vec<i16>, quality: i32, index: f32) -> f32 {
-    let ix = index.floor();
-    let fx = index - ix;
-    let mut tmp = 0f32;
-
-    for i in 1 - quality..quality + 1 {
-        tmp += vec[((ix + i as f32 + vec.len() as f32) % vec.len() as f32) as usize] as f32
-            * sinc(i as f32 - fx)
+impl EnvelopeState {
+    fn reset(&mut self, envelope: &Envelope) {
+        self.enabled = envelope.enabled && !envelope.nodes.is_empty();
+        self.tick = 0;
     }
 
-    tmp
+    // Returns true once the envelope has reached its end
+    fn process(&mut self, envelope: &Envelope, key_off: bool) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        self.value = envelope_value(envelope, self.tick);
+
+        let nodes = &envelope.nodes;
+        let node_tick = |index: u8| nodes[(index as usize).min(nodes.len() - 1)].tick;
+
+        self.tick = self.tick.saturating_add(1);
+        if envelope.sustain_enabled && !key_off {
+            if self.tick > node_tick(envelope.sustain_end) {
+                self.tick = node_tick(envelope.sustain_start)
+            }
+        } else if envelope.loop_enabled {
+            if self.tick > node_tick(envelope.loop_end) {
+                self.tick = node_tick(envelope.loop_start)
+            }
+        } else if self.tick > nodes[nodes.len() - 1].tick {
+            self.tick = nodes[nodes.len() - 1].tick;
+            return true;
+        }
+
+        false
+    }
 }
 
 const PERIOD: f32 = 3579545.25;
@@ -76,7 +193,244 @@ fn freq_from_period(period: u16) -> f32 {
     PERIOD / period as f32
 }
 
-impl Channel<'_> {
+impl<'a> Channel<'a> {
+    fn new(module: &'a Module, host_channel: u8) -> Channel<'a> {
+        Channel {
+            module,
+            host_channel,
+
+            current_sample_index: 0,
+            current_instrument: None,
+            note: 60,
+            new_note_action: NewNoteAction::Cut,
+            playing: false,
+            freq: 8363.0,
+            play_freq: 8363.0,
+            position: 0.0,
+            backwards: false,
+            looped: false,
+
+            porta_memory: 0,
+            tone_porta_memory: 0,
+            last_note: 0,
+            offset_memory: 0,
+            high_offset: 0,
+            volume_memory: 0,
+            retrigger_ticks: 0,
+            tempo_memory: 0,
+            loop_row: 0,
+            loop_count: 0,
+
+            vibrato_speed: 0,
+            vibrato_depth: 0,
+            vibrato_position: 0,
+            vibrato_delta: 0,
+
+            glissando: false,
+            snap_to_semitone: false,
+
+            key_off: false,
+            fading: false,
+            fade_volume: 1.0,
+
+            volume_envelope: EnvelopeState::default(),
+            pan_envelope: EnvelopeState::default(),
+            pitch_envelope: EnvelopeState::default(),
+
+            volume: 64.0,
+            panning: *module.channel_pan.get(host_channel as usize).unwrap_or(&32) as f32,
+            volume_swing: 0.0,
+            pan_swing: 0.0,
+            pitch_pan: 0.0,
+
+            gain: [0.0; 2],
+            gain_target: [0.0; 2],
+            gain_step: [0.0; 2],
+            ramp_frames: 0,
+            stopping: false,
+            channel_gain: 1.0,
+            pitch_factor: 1.0,
+        }
+    }
+
+    fn instrument(&self) -> Option<&'a Instrument> {
+        self.current_instrument
+            .map(|i| &self.module.instruments[i as usize])
+    }
+
+    fn has_envelope(&self, envelope: fn(&Instrument) -> &Envelope) -> bool {
+        self.instrument()
+            .is_some_and(|i| !envelope(i).nodes.is_empty())
+    }
+
+    // Starts the current sample over, envelopes included
+    fn restart(&mut self) {
+        self.playing = true;
+        self.position = 0.0;
+        self.backwards = false;
+        self.looped = false;
+
+        self.fade_in();
+        self.restart_envelopes();
+    }
+
+    // Starts the envelopes over and takes back key off and fade, the sample plays on
+    fn restart_envelopes(&mut self) {
+        self.key_off = false;
+        self.fading = false;
+        self.fade_volume = 1.0;
+
+        match self.instrument() {
+            Some(instrument) => {
+                self.new_note_action = instrument.new_note_action;
+                self.volume_envelope.reset(&instrument.volume_envelope);
+                self.pan_envelope.reset(&instrument.pan_envelope);
+                self.pitch_envelope.reset(&instrument.pitch_envelope);
+            }
+            None => {
+                self.new_note_action = NewNoteAction::Cut;
+                self.volume_envelope.enabled = false;
+                self.pan_envelope.enabled = false;
+                self.pitch_envelope.enabled = false;
+            }
+        }
+    }
+
+    fn trigger(&mut self, note: u8) {
+        self.note = note;
+        self.freq = 2f32.powf((note as f32 - 60.0) / 12.0)
+            * self.module.samples[self.current_sample_index as usize].base_frequency as f32;
+        self.restart();
+
+        // Spread the keyboard across the stereo field around the center note
+        self.pitch_pan = self.instrument().map_or(0.0, |i| {
+            (note as f32 - i.pitch_pan_center as f32) * i.pitch_pan_separation as f32 / 8.0
+        });
+    }
+
+    // Every new note gets its own random variation
+    fn swing(&mut self, rng: &mut Rng) {
+        (self.volume_swing, self.pan_swing) = match self.instrument() {
+            Some(instrument) => (
+                rng.swing(instrument.random_volume) / 100.0,
+                rng.swing(instrument.random_pan),
+            ),
+            None => (0.0, 0.0),
+        };
+    }
+
+    fn set_panning(&mut self, panning: f32) {
+        self.panning = panning;
+        self.pan_swing = 0.0;
+    }
+
+    fn note_off(&mut self) {
+        self.key_off = true;
+
+        // Leaving the sustain loop for the regular one, which hasn't been played yet
+        let sample = &self.module.samples[self.current_sample_index as usize];
+        if !matches!(sample.sustain_loop_type, LoopType::None) {
+            self.looped = false;
+        }
+
+        // Without a volume envelope to finish the note, or with one that never ends, fade it out
+        if let Some(instrument) = self.instrument() {
+            if !self.volume_envelope.enabled || instrument.volume_envelope.loop_enabled {
+                self.fading = true;
+            }
+        }
+    }
+
+    // Ramp up from silence on the next render
+    fn fade_in(&mut self) {
+        self.gain = [0.0; 2];
+        self.gain_target = [0.0; 2];
+        self.ramp_frames = 0;
+        self.stopping = false;
+    }
+
+    fn cut(&mut self) {
+        self.stopping = true;
+    }
+
+    fn note_fade(&mut self) {
+        // Only instruments have a fadeout
+        if self.current_instrument.is_some() {
+            self.fading = true;
+        }
+    }
+
+    fn process_envelopes(&mut self) {
+        if !self.playing {
+            return;
+        }
+        let Some(instrument) = self.instrument() else {
+            return;
+        };
+
+        if self
+            .volume_envelope
+            .process(&instrument.volume_envelope, self.key_off)
+        {
+            // A volume envelope ending in silence cuts the note, otherwise it fades out
+            if self.volume_envelope.value == 0.0 {
+                self.playing = false;
+            } else {
+                self.fading = true;
+            }
+        }
+        self.pan_envelope
+            .process(&instrument.pan_envelope, self.key_off);
+        self.pitch_envelope
+            .process(&instrument.pitch_envelope, self.key_off);
+
+        if self.fading {
+            self.fade_volume -= instrument.fadeout as f32 / 1024.0;
+            if self.fade_volume <= 0.0 {
+                self.fade_volume = 0.0;
+                self.playing = false;
+            }
+        }
+    }
+
+    fn mix_volume(&self, sample: &Sample) -> f32 {
+        let mut volume = (self.volume * (1.0 + self.volume_swing)).clamp(0.0, 64.0) / 64.0
+            * (sample.global_volume as f32 / 64.0);
+
+        if let Some(instrument) = self.instrument() {
+            volume *= (instrument.global_volume as f32 / 128.0) * self.fade_volume;
+            if self.volume_envelope.enabled {
+                volume *= self.volume_envelope.value / 64.0;
+            }
+        }
+
+        volume
+    }
+
+    fn mix_panning(&self) -> f32 {
+        let panning = (self.panning + self.pan_swing + self.pitch_pan).clamp(0.0, 64.0);
+
+        if self.pan_envelope.enabled {
+            // The envelope can only swing as far as the nearest side allows
+            panning + self.pan_envelope.value * (32.0 - (panning - 32.0).abs()) / 32.0
+        } else {
+            panning
+        }
+    }
+
+    // The sustain loop takes over until the note is released
+    fn active_loop(&self, sample: &Sample) -> (LoopType, u32, u32) {
+        if !self.key_off && !matches!(sample.sustain_loop_type, LoopType::None) {
+            (
+                sample.sustain_loop_type,
+                sample.sustain_loop_start,
+                sample.sustain_loop_end,
+            )
+        } else {
+            (sample.loop_type, sample.loop_start, sample.loop_end)
+        }
+    }
+
     fn porta_up(&mut self, linear: bool, mut value: u8) {
         if value != 0 {
             self.porta_memory = value;
@@ -127,16 +481,18 @@ impl Channel<'_> {
         }
     }
 
-    fn tone_portamento(&mut self, note: Note, linear: bool, mut value: u8) {
-        if value != 0 {
-            self.porta_memory = value;
+    fn tone_portamento(&mut self, linear: bool, mut value: u8) {
+        // Compatible Gxx gives Gxx its own memory, otherwise it's shared with Exx/Fxx
+        let memory = if self.module.compatible_gxx {
+            &mut self.tone_porta_memory
         } else {
-            value = self.porta_memory;
-        }
+            &mut self.porta_memory
+        };
 
-        match note {
-            Note::On(key) => self.last_note = key,
-            _ => {}
+        if value != 0 {
+            *memory = value;
+        } else {
+            value = *memory;
         }
 
         let desired_freq = 2f32.powf((self.last_note as f32 - 60.0) / 12.0)
@@ -170,6 +526,57 @@ impl Channel<'_> {
         }
     }
 
+    fn set_vibrato(&mut self, speed: u8, depth: u8) {
+        if speed != 0 {
+            self.vibrato_speed = speed;
+        }
+        if depth != 0 {
+            self.vibrato_depth = depth;
+        }
+    }
+
+    fn vibrato(&mut self, first_tick: bool) {
+        // Old Effects don't update vibrato on the first tick
+        if first_tick && self.module.old_effects {
+            return;
+        }
+
+        self.vibrato_position = self
+            .vibrato_position
+            .wrapping_add(self.vibrato_speed.wrapping_mul(4));
+        let sine = (64.0 * (self.vibrato_position as f32 * PI / 128.0).sin()).round() as i32;
+
+        // Old Effects vibrato is twice as deep
+        let shift = if self.module.old_effects { 5 } else { 6 };
+        self.vibrato_delta = (sine * self.vibrato_depth as i32) >> shift;
+    }
+
+    fn begin_tick(&mut self) {
+        self.vibrato_delta = 0;
+    }
+
+    fn update_play_freq(&mut self) {
+        let freq = if self.snap_to_semitone {
+            let base_frequency =
+                self.module.samples[self.current_sample_index as usize].base_frequency as f32;
+            let semitones = (12.0 * (self.freq / base_frequency).log2()).round();
+            base_frequency * 2f32.powf(semitones / 12.0)
+        } else {
+            self.freq
+        };
+
+        self.play_freq = if self.vibrato_delta != 0 {
+            freq * 2f32.powf(self.vibrato_delta as f32 / 768.0)
+        } else {
+            freq
+        };
+
+        // Pitch envelopes span 16 semitones in either direction
+        if self.pitch_envelope.enabled {
+            self.play_freq *= 2f32.powf(self.pitch_envelope.value / 24.0);
+        }
+    }
+
     fn vol_slide(&mut self, mut value: u8) {
         if value != 0 {
             self.volume_memory = value;
@@ -195,9 +602,8 @@ impl Channel<'_> {
         } else if upper != 0 && lower == 0xF {
             // fine up
             self.volume += upper as f32 / 8.0;
-        } else if upper != 0 && lower != 0 {
-            println!("Channel::vol_slide: invalid argument {:X}, ignoring", value);
         }
+        // Anything else is invalid and ignored
 
         if self.volume > 64.0 {
             self.volume = 64.0
@@ -207,6 +613,50 @@ impl Channel<'_> {
         }
     }
 
+    fn tempo_slide(&mut self, tempo: u8, mut value: u8) -> u8 {
+        // T00 reuses the last slide, direction included
+        if value != 0 {
+            self.tempo_memory = value;
+        } else {
+            value = self.tempo_memory;
+        }
+
+        let tempo = match value & 0xF0 {
+            0x10 => tempo as i16 + (value & 0x0F) as i16, // T1x
+            _ => tempo as i16 - (value & 0x0F) as i16,    // T0x
+        };
+
+        tempo.clamp(32, 255) as u8
+    }
+
+    fn sample_offset(&mut self, mut value: u8, out_of_range: OutOfRangeOffset) {
+        if value != 0 {
+            self.offset_memory = value;
+        } else {
+            value = self.offset_memory;
+        }
+
+        let sample = &self.module.samples[self.current_sample_index as usize];
+        let offset = self.high_offset as usize * 65536 + value as usize * 256;
+
+        if offset < sample.audio.len() {
+            self.position = offset as f64;
+            return;
+        }
+
+        match out_of_range {
+            OutOfRangeOffset::Ignore => self.position = 0.0,
+            OutOfRangeOffset::LoopStart => match sample.loop_type {
+                LoopType::None => self.playing = false,
+                _ => self.position = sample.loop_start as f64,
+            },
+            OutOfRangeOffset::Stop => {
+                self.position = sample.audio.len() as f64;
+                self.playing = false;
+            }
+        }
+    }
+
     fn retrigger(&mut self, value: u8) {
         match (value & 0xF0) >> 4 {
             // Volume change
@@ -233,6 +683,9 @@ impl Channel<'_> {
 
         if self.retrigger_ticks >= value & 0x0F {
             self.position = 0.0;
+            self.backwards = false;
+            self.looped = false;
+            self.fade_in();
             self.retrigger_ticks = 0;
         };
 
@@ -246,164 +699,987 @@ impl Channel<'_> {
         }
     }
 
-    fn process(&mut self, samplerate: u32, interpolation: Interpolation) -> i32 {
-        let sample = &self.module.samples[self.current_sample_index as usize];
-        if !self.playing || sample.audio.len() == 0 {
-            return 0;
-        };
+    // Moves one output frame ahead, returns false once the note has stopped
+    fn advance(&mut self, sample: &Sample, step: f64) -> bool {
+        let (loop_type, loop_start, loop_end) = self.active_loop(sample);
+        let (start, end) = (loop_start as f64, loop_end as f64);
 
         if self.backwards {
-            if self.position as u32 <= sample.loop_start {
-                self.backwards = false
-            } else {
-                self.position -= self.freq as f64 / samplerate as f64;
-            }
+            self.position -= step;
         } else {
-            self.position += self.freq as f64 / samplerate as f64;
+            self.position += step;
         }
 
-        if sample.loop_end > 0 {
-            if self.position as u32 > sample.loop_end - 1 {
-                match sample.loop_type {
-                    LoopType::Forward => self.position = sample.loop_start as f64,
-                    LoopType::PingPong => {
+        let looping = !matches!(loop_type, LoopType::None) && loop_end > loop_start;
+
+        match loop_type {
+            _ if !looping => {
+                if self.position < 0.0 || self.position >= sample.audio.len() as f64 {
+                    self.playing = false;
+                    self.backwards = false;
+                }
+            }
+            LoopType::Forward => {
+                if self.position >= end {
+                    self.position = start + (self.position - start) % (end - start);
+                    self.looped = true;
+                }
+            }
+            LoopType::PingPong => {
+                // The turning points aren't played twice
+                let last = end - 1.0;
+                if last <= start {
+                    self.position = start;
+                    return self.playing;
+                }
+
+                loop {
+                    if !self.backwards && self.position > last {
+                        self.position = 2.0 * last - self.position;
                         self.backwards = true;
-                        self.position -= self.freq as f64 / samplerate as f64;
-                    } // self.position -= 1.0 or 2.0 does not work as the program errors with out of bounds
-                    _ => {}
+                        self.looped = true;
+                    } else if self.backwards && self.position < start {
+                        self.position = 2.0 * start - self.position;
+                        self.backwards = false;
+                    } else {
+                        break;
+                    }
                 }
             }
+            LoopType::None => {}
         }
 
-        // Prevent out of bounds, but it doesn't seem to be working reliably
-        if self.position as usize >= sample.audio.len() - 1
-            && matches!(sample.loop_type, LoopType::None)
-        {
-            self.playing = false;
-            self.backwards = false;
+        self.playing
+    }
+
+    // Where a tap comes from, as if the active loop was unrolled around the position
+    fn tap_index(
+        &self,
+        index: i64,
+        (loop_type, loop_start, loop_end): (LoopType, u32, u32),
+    ) -> i64 {
+        let (start, end) = (loop_start as i64, loop_end as i64);
+        let length = end - start;
+
+        match loop_type {
+            _ if length <= 0 || index < start && !self.looped => index,
+            LoopType::Forward if index < start || index >= end => {
+                start + (index - start).rem_euclid(length)
+            }
+            LoopType::PingPong if length == 1 => start,
+            LoopType::PingPong => {
+                let period = 2 * (length - 1);
+                let offset = (index - start).rem_euclid(period);
+                if offset < length {
+                    start + offset
+                } else {
+                    start + period - offset
+                }
+            }
+            _ => index,
         }
+    }
 
-        if !self.playing {
-            return 0;
+    // Fills the interpolation window around the sample at `index`, silent past the end of a one-shot
+    fn fetch_taps(&self, sample: &Sample, index: i64, taps: &mut [f32]) {
+        let active_loop = self.active_loop(sample);
+        let (loop_type, loop_start, loop_end) = active_loop;
+        let first = index - (taps.len() as i64 - 1) / 2;
+        let last = first + taps.len() as i64 - 1;
+
+        // Most of the time the whole window can be copied straight from the sample
+        let length = sample.audio.len() as i64;
+        let (lower, upper) = match loop_type {
+            _ if loop_end <= loop_start => (0, length),
+            LoopType::None => (0, length),
+            _ if self.looped => (loop_start as i64, length.min(loop_end as i64)),
+            _ => (0, length.min(loop_end as i64)),
         };
 
-        match interpolation {
-            Interpolation::None => {
-                (((sample.audio[self.position as usize]) as i32 * 32768) as f32
-                    * (self.volume as f32 / 64.0)
-                    * (sample.global_volume as f32 / 64.0)) as i32
+        if first >= lower && last < upper {
+            for (tap, value) in taps.iter_mut().zip(&sample.audio[first as usize..]) {
+                *tap = *value as f32;
             }
+            return;
+        }
+
+        for (i, tap) in taps.iter_mut().enumerate() {
+            let index = self.tap_index(first + i as i64, active_loop);
+            *tap = usize::try_from(index)
+                .ok()
+                .and_then(|i| sample.audio.get(i))
+                .map_or(0.0, |v| *v as f32);
+        }
+    }
+
+    // Mixes the voice into the buffer, the caller makes sure no tick happens in between
+    fn render(
+        &mut self,
+        out: &mut [[f32; 2]],
+        samplerate: u32,
+        interpolation: Interpolation,
+        ramp: u32,
+    ) {
+        match interpolation {
+            Interpolation::None => self.render_with::<1>(out, samplerate, ramp, |taps, _| taps[0]),
             Interpolation::Linear => {
-                (((vec_linear(&sample.audio, (self.position - 1.0) as f32)) as i32 * 32768) as f32
-                    * (self.volume / 64.0)
-                    * (sample.global_volume as f32 / 64.0)) as i32
+                self.render_with::<2>(out, samplerate, ramp, interpolation::linear)
+            }
+            Interpolation::Cubic => {
+                self.render_with::<4>(out, samplerate, ramp, interpolation::cubic)
+            }
+            Interpolation::Sinc8 => {
+                let table = interpolation::sinc_table(8);
+                self.render_with::<8>(out, samplerate, ramp, |taps, f| table.interpolate(taps, f))
             }
             Interpolation::Sinc16 => {
-                (((vec_sinc(&sample.audio, 16, self.position as f32)) as i32 * 32768) as f32
-                    * (self.volume as f32 / 64.0)
-                    * (sample.global_volume as f32 / 64.0)) as i32
+                let table = interpolation::sinc_table(16);
+                self.render_with::<16>(out, samplerate, ramp, |taps, f| table.interpolate(taps, f))
             }
             Interpolation::Sinc32 => {
-                (((vec_sinc(&sample.audio, 32, self.position as f32)) as i32 * 32768) as f32
-                    * (self.volume as f32 / 64.0)
-                    * (sample.global_volume as f32 / 64.0)) as i32
+                let table = interpolation::sinc_table(32);
+                self.render_with::<32>(out, samplerate, ramp, |taps, f| table.interpolate(taps, f))
+            }
+        }
+    }
+
+    fn gain_for(&self, sample: &Sample) -> [f32; 2] {
+        let volume = if self.stopping {
+            0.0
+        } else {
+            self.mix_volume(sample) / 32768.0 * self.channel_gain
+        };
+        let pan = self.mix_panning() / 64.0;
+        [volume * (1.0 - pan), volume * pan]
+    }
+
+    // Moves ahead as if the frames had been rendered, without ramps
+    fn skip(&mut self, frames: u32, samplerate: u32) {
+        let sample = &self.module.samples[self.current_sample_index as usize];
+        if self.stopping || sample.audio.is_empty() {
+            self.playing = false;
+            return;
+        }
+
+        self.gain = self.gain_for(sample);
+        self.gain_target = self.gain;
+        self.ramp_frames = 0;
+
+        self.advance(
+            sample,
+            (self.play_freq * self.pitch_factor) as f64 / samplerate as f64 * frames as f64,
+        );
+    }
+
+    // Starts ramping towards new gains when volume or panning changed
+    fn set_gain_target(&mut self, target: [f32; 2], ramp: u32) {
+        if target == self.gain_target {
+            return;
+        }
+
+        self.gain_target = target;
+        if ramp == 0 {
+            self.gain = target;
+            self.ramp_frames = 0;
+        } else {
+            self.gain_step = [
+                (target[0] - self.gain[0]) / ramp as f32,
+                (target[1] - self.gain[1]) / ramp as f32,
+            ];
+            self.ramp_frames = ramp;
+        }
+    }
+
+    fn render_with<const TAPS: usize>(
+        &mut self,
+        out: &mut [[f32; 2]],
+        samplerate: u32,
+        ramp: u32,
+        interpolate: impl Fn(&[f32], f32) -> f32,
+    ) {
+        let module = self.module;
+        let sample = &module.samples[self.current_sample_index as usize];
+        if !self.playing || sample.audio.is_empty() {
+            return;
+        };
+
+        // Volume and panning only change on ticks
+        self.set_gain_target(self.gain_for(sample), ramp);
+
+        let step = (self.play_freq * self.pitch_factor) as f64 / samplerate as f64;
+        let mut taps = [0.0; TAPS];
+
+        for frame in out.iter_mut() {
+            if self.ramp_frames > 0 {
+                self.ramp_frames -= 1;
+                if self.ramp_frames == 0 {
+                    self.gain = self.gain_target;
+                } else {
+                    self.gain[0] += self.gain_step[0];
+                    self.gain[1] += self.gain_step[1];
+                }
+            } else if self.stopping {
+                self.playing = false;
+                break;
             }
+
+            if !self.advance(sample, step) {
+                break;
+            }
+
+            let index = self.position.floor();
+            self.fetch_taps(sample, index as i64, &mut taps);
+
+            let value = interpolate(&taps, (self.position - index) as f32);
+            frame[0] += value * self.gain[0];
+            frame[1] += value * self.gain[1];
         }
     }
 }
 
+#[derive(Default, Debug, Clone, Copy)]
+pub struct SongLength {
+    pub duration: Duration, // Until the song starts repeating itself
+    pub loop_position: u8,  // Where it repeats from
+    pub loop_row: u16,
+    pub loop_time: Duration,
+}
+
+// Songs that take longer are assumed to never repeat
+const MAX_SONG_LENGTH: f64 = 4.0 * 3600.0;
+
 pub struct Player<'a> {
     pub module: &'a Module,
 
     pub samplerate: u32,
     pub interpolation: Interpolation,
-
-    pub current_position: u8,
-    pub current_pattern: u8,
+    pub tempo_mode: TempoMode,
+    pub out_of_range_offset: OutOfRangeOffset,
+    pub preamp: f32,
+    pub limiter: bool,
+    pub volume_ramp: u32, // Frames, 0 disables ramping
+    pub end_of_song: EndOfSong,
+
+    current_position: u8,
+    current_pattern: u8,
     current_row: u16,
+    start_position: u8, // Of the subsong, the end of the song goes back here
 
     current_tempo: u8,
     current_speed: u8,
 
-    tick_counter: u32,
+    tick_frames: u32,   // Left until the next tick
+    tick_fraction: f64, // Carried over so ticks average out to their exact length
     ticks_passed: u8,
+    row_repeats: u8, // SEx
+
+    loop_count: u32,
+    ended: bool,
+    fade: Option<(u32, u32)>, // Frames left and in total of the fade out at the end
+    seek_target: Option<(u8, u16)>,
+
+    frame: u64, // Output frames mixed so far, the timestamp of events
+    events: Option<EventSender>,
+    controls: Arc<ChannelControls>,
+    playback_controls: Arc<PlaybackControls>,
 
     channels: [Channel<'a>; 64],
+    background: Vec<Channel<'a>>,
+    mix_buffer: Vec<[f32; 2]>,
+    stem_buffer: Vec<[f32; 2]>, // MIX_BUFFER_FRAMES per pattern channel, only allocated for stems
+
+    rng: Rng,
+}
+
+// The first order from the given one that isn't a "+++" separator, None at the end of the song
+fn playable_position(module: &Module, from: usize) -> Option<u8> {
+    module
+        .playlist
+        .iter()
+        .enumerate()
+        .skip(from)
+        .find(|(_, &pattern)| pattern != 254)
+        .filter(|(_, &pattern)| pattern != 255)
+        .map(|(position, _)| position as u8)
+}
+
+fn past_notes<'b, 'a>(
+    background: &'b mut [Channel<'a>],
+    channel: usize,
+) -> impl Iterator<Item = &'b mut Channel<'a>> {
+    background
+        .iter_mut()
+        .filter(move |v| v.playing && v.host_channel as usize == channel)
+}
+
+// Loudness the module was made for, with less gain the more channels can play at once
+fn default_preamp(module: &Module) -> f32 {
+    let channels = module.used_channels().len().max(4);
+
+    module.mixing_volume as f32 / 64.0 / (channels as f32 / 4.0).sqrt()
+}
+
+// Leaves everything below the threshold alone and bends the rest towards full scale
+fn soft_limit(value: f32) -> f32 {
+    const THRESHOLD: f32 = 0.75;
+
+    if value.abs() <= THRESHOLD {
+        return value;
+    }
+    let over = (value.abs() - THRESHOLD) / (1.0 - THRESHOLD);
+    value.signum() * (THRESHOLD + (1.0 - THRESHOLD) * over.tanh())
 }
 
 impl Player<'_> {
     pub fn from_module(module: &Module, samplerate: u32) -> Player<'_> {
+        Interpolation::prepare();
+        let start = playable_position(module, 0);
+
         Player {
             module,
 
             samplerate,
             interpolation: Interpolation::Linear,
-
-            current_position: 0,
-            current_pattern: module.playlist[0],
+            tempo_mode: module.tempo_mode,
+            out_of_range_offset: if module.old_effects {
+                OutOfRangeOffset::Stop
+            } else {
+                OutOfRangeOffset::Ignore
+            },
+            preamp: default_preamp(module),
+            limiter: false,
+            volume_ramp: samplerate / 750, // About 1.3 ms
+            end_of_song: EndOfSong::Stop,
+
+            current_position: start.unwrap_or(0),
+            current_pattern: start.map_or(255, |p| module.playlist[p as usize]),
             current_row: 65535,
+            start_position: start.unwrap_or(0),
 
             current_tempo: module.initial_tempo,
             current_speed: module.initial_speed,
 
-            tick_counter: 0,
-            ticks_passed: 0,
+            tick_frames: 0,
+            tick_fraction: 0.0,
+            ticks_passed: module.initial_speed.saturating_sub(1), // The first tick plays the first row
+
+            row_repeats: 0,
 
-            channels: array::from_fn(|_| Channel {
-                module: module,
+            loop_count: 0,
+            ended: start.is_none(), // Nothing to play
+            fade: None,
+            seek_target: None,
 
-                current_sample_index: 0,
-                playing: false,
-                freq: 8363.0,
-                position: 0.0,
-                backwards: false,
+            frame: 0,
+            events: None,
+            controls: Arc::default(),
+            playback_controls: Arc::default(),
 
-                porta_memory: 0,
-                last_note: 0,
-                offset_memory: 0,
-                volume_memory: 0,
-                retrigger_ticks: 0,
+            channels: array::from_fn(|i| Channel::new(module, i as u8)),
+            background: vec![Channel::new(module, 0); BACKGROUND_VOICES],
+            mix_buffer: vec![[0.0; 2]; MIX_BUFFER_FRAMES],
+            stem_buffer: Vec::new(),
 
-                volume: 64.0,
-                // panning: 0
-            }),
+            rng: Rng::new(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |t| t.as_nanos() as u64),
+            ),
         }
     }
 
-    pub fn process(&mut self) -> i32 {
-        let mut out = 0i32;
+    // Fixes the random volume and pan swing, so that renders are reproducible
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
 
-        for c in self.channels.iter_mut() {
-            if c.playing {
-                out = out.saturating_add(c.process(self.samplerate, self.interpolation));
+    // Fast-forwards the song logic without mixing, so tempo, speed, pattern loops, effect memories
+    // and held notes are what they would be when playing up to there.
+    // Returns false if the song never gets there, leaving it at the start.
+    pub fn seek(&mut self, position: u8, row: u16) -> bool {
+        let found = self.silently(|player| {
+            player.reset();
+            if player.ended {
+                return false;
             }
-        }
+            player.seek_target = Some((position, row));
+
+            loop {
+                player.tick();
+                player.schedule_tick();
+                if player.loop_count > 0 {
+                    player.reset();
+                    return false;
+                }
+                if player.seek_target.is_none() {
+                    return true;
+                }
 
-        if self.tick_counter >= ((self.samplerate as f32 * 2.5) / self.current_tempo as f32) as u32
-        {
-            self.ticks_passed += 1;
-            self.tick_counter = 0;
-            if self.ticks_passed >= self.current_speed {
-                self.advance_row();
-                self.play_row();
+                player.skip_voices(player.tick_frames);
+                player.tick_frames = 0;
             }
-            self.process_tick();
-        } else {
-            self.tick_counter += 1;
+        });
+        if found {
+            self.emit_seek();
         }
-
-        out
+        found
     }
 
-    fn process_tick(&mut self) {
-        if self.current_row == 65535 {
-            return;
-        };
-        let row = &self.module.patterns[self.current_pattern as usize][self.current_row as usize];
+    // Same as seek, to a point in time. Returns false if the song is shorter than that.
+    pub fn seek_time(&mut self, time: Duration) -> bool {
+        let found = self.silently(|player| {
+            player.reset();
+            if player.ended {
+                return false;
+            }
 
-        if self.ticks_passed <= 0 {return};
+            let mut frames = (time.as_secs_f64() * player.samplerate as f64) as u64;
+            while frames > 0 {
+                if player.tick_frames == 0 {
+                    player.tick();
+                    player.schedule_tick();
+                    if player.loop_count > 0 {
+                        player.reset();
+                        return false;
+                    }
+                }
+
+                let skip = frames.min(player.tick_frames as u64) as u32;
+                player.skip_voices(skip);
+                player.tick_frames -= skip;
+                frames -= skip as u64;
+            }
+
+            true
+        });
+        if found {
+            self.emit_seek();
+        }
+        found
+    }
+
+    // Runs the song logic without telling the host, only where it ends up matters
+    fn silently(&mut self, f: impl FnOnce(&mut Self) -> bool) -> bool {
+        let events = self.events.take();
+        let result = f(self);
+        self.events = events;
+        result
+    }
+
+    // Where playback continues after a seek, and at which tempo and speed
+    fn emit_seek(&mut self) {
+        if self.current_row != 65535 {
+            self.emit_position();
+            self.emit(EventKind::Row {
+                position: self.current_position,
+                pattern: self.current_pattern,
+                row: self.current_row,
+            });
+        }
+        self.emit(EventKind::Tempo(self.current_tempo));
+        self.emit(EventKind::Speed(self.current_speed));
+    }
+
+    // From now on row, order, note, tempo and end of song events go to the returned receiver,
+    // replacing any earlier one. Events that don't fit in the queue are dropped.
+    pub fn events(&mut self, capacity: usize) -> EventReceiver {
+        let (sender, receiver) = event_queue(capacity);
+        self.events = Some(sender);
+        receiver
+    }
+
+    // Mute, solo and gain of the pattern channels, changes are heard from the next rendered block
+    pub fn controls(&self) -> Arc<ChannelControls> {
+        self.controls.clone()
+    }
+
+    // Transpose, pitch and tempo factor, changes are heard from the next rendered block or tick
+    pub fn playback_controls(&self) -> Arc<PlaybackControls> {
+        self.playback_controls.clone()
+    }
+
+    // Output frames mixed so far, to compare event timestamps against
+    pub fn frames_played(&self) -> u64 {
+        self.frame
+    }
+
+    fn emit(&mut self, kind: EventKind) {
+        if let Some(events) = &mut self.events {
+            events.push(Event {
+                frame: self.frame,
+                kind,
+            });
+        }
+    }
+
+    fn emit_position(&mut self) {
+        self.emit(EventKind::Position {
+            position: self.current_position,
+            pattern: self.current_pattern,
+        });
+    }
+
+    // Plays the song logic from the start without mixing until it revisits a row it has
+    // already played, rows inside a pattern loop don't count as they play differently each time
+    pub fn song_length(&self) -> SongLength {
+        self.analyse(
+            self.start_position,
+            &mut vec![false; self.module.playlist.len()],
+        )
+    }
+
+    // Start positions of the independent songs in the playlist, as used by game soundtracks.
+    // Every order that can't be reached from an earlier subsong starts a new one.
+    pub fn subsongs(&self) -> Vec<u8> {
+        let mut visited = vec![false; self.module.playlist.len()];
+        let mut subsongs = Vec::new();
+
+        while let Some(start) = (0..visited.len())
+            .find(|&p| !visited[p] && playable_position(self.module, p) == Some(p as u8))
+        {
+            visited[start] = true;
+            self.analyse(start as u8, &mut visited);
+            subsongs.push(start as u8);
+        }
+
+        subsongs
+    }
+
+    // Plays a subsong from now on, false if there is nothing to play at that position
+    pub fn set_start_position(&mut self, position: u8) -> bool {
+        if playable_position(self.module, position as usize) != Some(position) {
+            return false;
+        }
+
+        self.start_position = position;
+        self.reset();
+        true
+    }
+
+    // Song length from a start position, marking the orders that get played
+    fn analyse(&self, start: u8, visited_positions: &mut [bool]) -> SongLength {
+        if playable_position(self.module, start as usize) != Some(start) {
+            return SongLength::default(); // Nothing to play
+        }
+
+        let mut player = Player::from_module(self.module, self.samplerate);
+        player.tempo_mode = self.tempo_mode;
+        player.playback_controls = self.playback_controls.clone();
+        player.end_of_song = EndOfSong::LoopForever;
+        player.start_position = start;
+        player.current_position = start;
+        player.current_pattern = self.module.playlist[start as usize];
+
+        let mut visited = HashMap::new();
+        let mut time = 0.0;
+
+        while time < MAX_SONG_LENGTH {
+            let new_row =
+                player.ticks_passed + 1 >= player.current_speed && player.row_repeats == 0;
+            player.tick();
+
+            visited_positions[player.current_position as usize] = true;
+
+            let in_pattern_loop = player.channels.iter().any(|c| c.loop_count > 0);
+            if new_row && !in_pattern_loop {
+                let row = (player.current_position, player.current_row);
+                if let Some(&loop_time) = visited.get(&row) {
+                    return SongLength {
+                        duration: Duration::from_secs_f64(time),
+                        loop_position: row.0,
+                        loop_row: row.1,
+                        loop_time: Duration::from_secs_f64(loop_time),
+                    };
+                }
+                visited.insert(row, time);
+            }
+
+            time += player.samples_per_tick() / player.samplerate as f64;
+        }
+
+        SongLength {
+            duration: Duration::from_secs_f64(time),
+            loop_position: 0,
+            loop_row: 0,
+            loop_time: Duration::ZERO,
+        }
+    }
+
+    // Back to the start of the song, keeping the settings
+    fn reset(&mut self) {
+        let mut player = Player::from_module(self.module, self.samplerate);
+
+        player.interpolation = self.interpolation;
+        player.tempo_mode = self.tempo_mode;
+        player.out_of_range_offset = self.out_of_range_offset;
+        player.preamp = self.preamp;
+        player.limiter = self.limiter;
+        player.volume_ramp = self.volume_ramp;
+        player.end_of_song = self.end_of_song;
+        std::mem::swap(&mut player.rng, &mut self.rng);
+        player.events = self.events.take();
+        player.controls = self.controls.clone();
+        player.playback_controls = self.playback_controls.clone();
+        player.frame = self.frame;
+
+        player.start_position = self.start_position;
+        player.current_position = self.start_position;
+        // Nothing to play when the playlist is empty, from_module has already ended it then
+        player.current_pattern = self
+            .module
+            .playlist
+            .get(self.start_position as usize)
+            .map_or(255, |&pattern| pattern);
+
+        *self = player;
+    }
+
+    fn skip_voices(&mut self, frames: u32) {
+        self.apply_controls();
+        for c in self.channels.iter_mut().chain(self.background.iter_mut()) {
+            if c.playing {
+                c.skip(frames, self.samplerate);
+            }
+        }
+    }
+
+    fn apply_controls(&mut self) {
+        let pitch_factor = self.playback_controls.pitch_factor();
+        for c in self.channels.iter_mut().chain(self.background.iter_mut()) {
+            let channel = c.host_channel as usize;
+            c.channel_gain = if self.module.channel_enabled.get(channel) == Some(&false) {
+                0.0
+            } else {
+                self.controls.effective_gain(channel)
+            };
+            c.pitch_factor = pitch_factor;
+        }
+    }
+
+    // The song has played as many times as it should, everything after is silence
+    pub fn ended(&self) -> bool {
+        self.ended
+    }
+
+    // Order in the playlist, change it with seek or set_start_position
+    pub fn current_position(&self) -> u8 {
+        self.current_position
+    }
+
+    pub fn current_pattern(&self) -> u8 {
+        self.current_pattern
+    }
+
+    // Row of the current pattern, only valid once the first row has played
+    pub fn current_row(&self) -> u16 {
+        self.current_row
+    }
+
+    pub fn current_tempo(&self) -> u8 {
+        self.current_tempo
+    }
+
+    pub fn current_speed(&self) -> u8 {
+        self.current_speed
+    }
+
+    // Times the song reached its end or jumped back so far
+    pub fn loops_played(&self) -> u32 {
+        self.loop_count
+    }
+
+    // Mono, both sides summed, returns how many frames were played before the song ended
+    pub fn render(&mut self, out: &mut [f32]) -> usize {
+        let mut rendered = 0;
+        for block in out.chunks_mut(MIX_BUFFER_FRAMES) {
+            rendered += self.mix(block.len(), false);
+            for (o, frame) in block.iter_mut().zip(self.mix_buffer.iter()) {
+                *o = self.master((frame[0] + frame[1]) * self.preamp);
+            }
+        }
+        rendered
+    }
+
+    // Interleaved stereo, returns how many frames were played before the song ended
+    pub fn render_stereo(&mut self, out: &mut [f32]) -> usize {
+        let mut rendered = 0;
+        for block in out.chunks_mut(MIX_BUFFER_FRAMES * 2) {
+            rendered += self.mix(block.len() / 2, false);
+            self.write_mix(block);
+        }
+        rendered
+    }
+
+    // Like render_stereo, and also every pattern channel with its past notes on its own.
+    // stems[n] is channel n and as long as out, or empty to skip it. Only the full mix is limited.
+    pub fn render_stems(&mut self, out: &mut [f32], stems: &mut [Vec<f32>]) -> usize {
+        if self.stem_buffer.is_empty() {
+            self.stem_buffer = vec![[0.0; 2]; 64 * MIX_BUFFER_FRAMES];
+        }
+
+        let mut rendered = 0;
+        for (i, block) in out.chunks_mut(MIX_BUFFER_FRAMES * 2).enumerate() {
+            let frames = block.len() / 2;
+            rendered += self.mix(frames, true);
+            self.write_mix(block);
+
+            for (channel, stem) in stems.iter_mut().take(64).enumerate() {
+                if stem.is_empty() {
+                    continue;
+                }
+
+                let start = i * MIX_BUFFER_FRAMES * 2;
+                let source = &self.stem_buffer[channel * MIX_BUFFER_FRAMES..][..frames];
+                for (o, frame) in stem[start..start + frames * 2]
+                    .chunks_exact_mut(2)
+                    .zip(source)
+                {
+                    o[0] = frame[0] * self.preamp;
+                    o[1] = frame[1] * self.preamp;
+                }
+            }
+        }
+        rendered
+    }
+
+    fn write_mix(&self, out: &mut [f32]) {
+        for (o, frame) in out.chunks_exact_mut(2).zip(self.mix_buffer.iter()) {
+            o[0] = self.master(frame[0] * self.preamp);
+            o[1] = self.master(frame[1] * self.preamp);
+        }
+    }
+
+    fn master(&self, value: f32) -> f32 {
+        if self.limiter {
+            soft_limit(value)
+        } else {
+            value
+        }
+    }
+
+    // Fills the start of the mix buffer, one run of samples per tick.
+    // With stems every voice goes to its channel's part of the stem buffer first.
+    fn mix(&mut self, frames: usize, stems: bool) -> usize {
+        let _guard = RenderGuard::enter();
+        self.apply_controls();
+        self.mix_buffer[..frames].fill([0.0; 2]);
+        if stems {
+            for stem in self.stem_buffer.chunks_exact_mut(MIX_BUFFER_FRAMES) {
+                stem[..frames].fill([0.0; 2]);
+            }
+        }
+
+        let mut done = 0;
+        while done < frames && !self.ended {
+            if self.tick_frames == 0 {
+                self.tick();
+                self.schedule_tick();
+                continue;
+            }
+
+            let mut run = (self.tick_frames as usize).min(frames - done);
+            if let Some((left, _)) = self.fade {
+                run = run.min(left as usize);
+            }
+
+            let buffer = &mut self.mix_buffer[done..done + run];
+            for c in self.channels.iter_mut().chain(self.background.iter_mut()) {
+                if c.playing {
+                    let buffer = if stems {
+                        let start = c.host_channel as usize * MIX_BUFFER_FRAMES + done;
+                        &mut self.stem_buffer[start..start + run]
+                    } else {
+                        &mut *buffer
+                    };
+                    c.render(
+                        buffer,
+                        self.samplerate,
+                        self.interpolation,
+                        self.volume_ramp,
+                    );
+                }
+            }
+
+            if let Some((left, total)) = self.fade {
+                self.fade_out(done..done + run, left, total, stems);
+
+                let left = left - run as u32;
+                self.fade = Some((left, total));
+                if left == 0 {
+                    self.ended = true;
+                    self.emit(EventKind::EndOfSong);
+                }
+            }
+
+            self.tick_frames -= run as u32;
+            self.frame += run as u64;
+            done += run;
+        }
+
+        if stems {
+            for stem in self.stem_buffer.chunks_exact(MIX_BUFFER_FRAMES) {
+                for (frame, s) in self.mix_buffer[..done].iter_mut().zip(stem) {
+                    frame[0] += s[0];
+                    frame[1] += s[1];
+                }
+            }
+        }
+
+        done
+    }
+
+    fn tick(&mut self) {
+        for c in self.channels.iter_mut().chain(self.background.iter_mut()) {
+            c.begin_tick();
+        }
+
+        let first = self.current_row == 65535;
+        let (position, tempo, speed) = (
+            self.current_position,
+            self.current_tempo,
+            self.current_speed,
+        );
+
+        self.ticks_passed += 1;
+        if self.ticks_passed >= self.current_speed {
+            if self.row_repeats > 0 {
+                // SEx plays the row again without retriggering its notes
+                self.row_repeats -= 1;
+                self.ticks_passed = 0;
+            } else {
+                self.advance_row();
+                if self.ended {
+                    return; // Nothing after the end gets heard
+                }
+
+                if first || self.current_position != position {
+                    self.emit_position();
+                }
+                self.emit(EventKind::Row {
+                    position: self.current_position,
+                    pattern: self.current_pattern,
+                    row: self.current_row,
+                });
+
+                if self.seek_target == Some((self.current_position, self.current_row)) {
+                    self.seek_target = None;
+                }
+                self.play_row();
+            }
+        }
+        self.process_tick();
+
+        if first || self.current_tempo != tempo {
+            self.emit(EventKind::Tempo(self.current_tempo));
+        }
+        if first || self.current_speed != speed {
+            self.emit(EventKind::Speed(self.current_speed));
+        }
+
+        for c in self.channels.iter_mut().chain(self.background.iter_mut()) {
+            c.process_envelopes();
+            c.update_play_freq();
+        }
+    }
+
+    // Hands the channel's note over to a background voice according to its NNA
+    fn move_to_background(&mut self, channel: usize) {
+        let action = self.channels[channel].new_note_action;
+        if matches!(action, NewNoteAction::Cut) && self.volume_ramp == 0 {
+            return;
+        }
+
+        let slot = self.copy_to_background(channel);
+        let voice = &mut self.background[slot];
+        match action {
+            NewNoteAction::Cut => voice.cut(), // Still gets a short fade out
+            NewNoteAction::NoteOff => voice.note_off(),
+            NewNoteAction::NoteFade => voice.note_fade(),
+            NewNoteAction::Continue => {}
+        }
+    }
+
+    // Copies the channel's note to a background voice, returns its slot
+    fn copy_to_background(&mut self, channel: usize) -> usize {
+        // Take a free voice, or steal the quietest one
+        let slot = match self.background.iter().position(|v| !v.playing) {
+            Some(slot) => slot,
+            None => self
+                .background
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| {
+                    (a.volume * a.fade_volume).total_cmp(&(b.volume * b.fade_volume))
+                })
+                .map(|(slot, _)| slot)
+                .unwrap(),
+        };
+
+        self.background[slot] = self.channels[channel].clone();
+        slot
+    }
+
+    fn fade_out(&mut self, frames: Range<usize>, left: u32, total: u32, stems: bool) {
+        let gain = |i: usize| (left - i as u32) as f32 / total as f32;
+
+        if stems {
+            for stem in self.stem_buffer.chunks_exact_mut(MIX_BUFFER_FRAMES) {
+                for (i, frame) in stem[frames.clone()].iter_mut().enumerate() {
+                    frame[0] *= gain(i);
+                    frame[1] *= gain(i);
+                }
+            }
+        } else {
+            for (i, frame) in self.mix_buffer[frames].iter_mut().enumerate() {
+                frame[0] *= gain(i);
+                frame[1] *= gain(i);
+            }
+        }
+    }
+
+    // Sets the length of the tick that was just processed
+    fn schedule_tick(&mut self) {
+        let length = self.samples_per_tick() + self.tick_fraction;
+        self.tick_frames = length as u32;
+        self.tick_fraction = length - self.tick_frames as f64;
+    }
+
+    fn samples_per_tick(&self) -> f64 {
+        let tempo = self.current_tempo as f64 * self.playback_controls.tempo_factor() as f64;
+
+        match self.tempo_mode {
+            TempoMode::Classic => self.samplerate as f64 * 2.5 / tempo,
+            TempoMode::Alternative => self.samplerate as f64 / tempo,
+            TempoMode::Modern => {
+                self.samplerate as f64 * 60.0
+                    / (tempo * self.current_speed as f64 * self.module.rows_per_beat as f64)
+            }
+        }
+    }
+
+    fn process_tick(&mut self) {
+        if self.current_row == 65535 {
+            return;
+        };
+        let row = &self.module.patterns[self.current_pattern as usize][self.current_row as usize];
+
+        if self.ticks_passed == 0 {
+            return;
+        };
 
         for (i, col) in row.iter().enumerate() {
+            // Fade out what was playing instead of jumping back to the start
+            if let Effect::Retrig(value) = col.effect {
+                let channel = &self.channels[i];
+                if self.volume_ramp > 0
+                    && channel.playing
+                    && channel.retrigger_ticks >= value & 0x0F
+                {
+                    let slot = self.copy_to_background(i);
+                    self.background[slot].cut();
+                }
+            }
+
             let channel = &mut self.channels[i];
 
             match col.effect {
@@ -412,12 +1688,31 @@ impl Player<'_> {
                     channel.porta_down(self.module.linear_freq_slides, value)
                 }
                 Effect::TonePorta(value) => {
-                    channel.tone_portamento(col.note, self.module.linear_freq_slides, value)
+                    channel.tone_portamento(self.module.linear_freq_slides, value)
                 }
+                Effect::Vibrato(_) | Effect::FineVibrato(_) => channel.vibrato(false),
                 Effect::VolSlide(value) => channel.vol_slide(value),
+                Effect::VolSlideVibrato(value) => {
+                    channel.vol_slide(value);
+                    channel.vibrato(false)
+                }
+                Effect::VolSlideTonePorta(value) => {
+                    channel.vol_slide(value);
+                    channel.tone_portamento(self.module.linear_freq_slides, 0)
+                }
                 Effect::Retrig(value) => channel.retrigger(value),
+                Effect::DecTempo(value) => {
+                    self.current_tempo = channel.tempo_slide(self.current_tempo, value)
+                }
+                Effect::IncTempo(value) => {
+                    self.current_tempo = channel.tempo_slide(self.current_tempo, 0x10 | value)
+                }
                 _ => {}
             }
+
+            if matches!(col.vol, VolEffect::VibratoDepth(_)) {
+                channel.vibrato(false)
+            }
         }
     }
 
@@ -435,10 +1730,12 @@ impl Player<'_> {
         let mut pat_break_enabled = false;
         let mut pat_break_to = 0u8;
 
-        for col in row.iter() {
+        let mut loop_to = None;
+
+        for (i, col) in row.iter().enumerate() {
+            let channel = &mut self.channels[i];
+
             match col.effect {
-                Effect::SetSpeed(speed) => self.current_speed = speed,
-                Effect::SetTempo(tempo) => self.current_tempo = tempo,
                 Effect::PosJump(position) => {
                     pos_jump_enabled = true;
                     pos_jump_to = position
@@ -447,61 +1744,148 @@ impl Player<'_> {
                     pat_break_enabled = true;
                     pat_break_to = row
                 }
+                Effect::PatLoopStart => channel.loop_row = self.current_row,
+                Effect::PatLoop(count) => {
+                    if channel.loop_count == 0 {
+                        channel.loop_count = count;
+                        loop_to = Some(channel.loop_row);
+                    } else {
+                        channel.loop_count -= 1;
+                        if channel.loop_count > 0 {
+                            loop_to = Some(channel.loop_row);
+                        } else {
+                            // A finished loop can't be jumped back into
+                            channel.loop_row = self.current_row + 1;
+                        }
+                    }
+                }
                 _ => {}
             }
         }
 
         self.ticks_passed = 0;
+
+        // Pattern loops win over jumps and breaks
+        if let Some(row) = loop_to {
+            self.current_row = row;
+            return;
+        }
+
+        let position = self.current_position;
+        self.advance_position(
+            pos_jump_enabled,
+            pos_jump_to,
+            pat_break_enabled,
+            pat_break_to,
+        );
+
+        // Pattern loops start over in a new pattern
+        if self.current_position != position {
+            for channel in self.channels.iter_mut() {
+                channel.loop_row = 0;
+                channel.loop_count = 0;
+            }
+        }
+    }
+
+    fn advance_position(
+        &mut self,
+        pos_jump_enabled: bool,
+        pos_jump_to: u8,
+        pat_break_enabled: bool,
+        pat_break_to: u8,
+    ) {
         if self.current_row == self.module.patterns[self.current_pattern as usize].len() as u16 {
             self.current_row = 0;
         } else {
             self.current_row += 1;
             if pos_jump_enabled {
                 self.current_row = 0;
-                self.current_position = pos_jump_to;
-                self.current_pattern = self.module.playlist[self.current_position as usize];
+                self.go_to_position(pos_jump_to as usize, pos_jump_to <= self.current_position);
             }
 
             if pat_break_enabled {
                 self.current_row = pat_break_to as u16;
-                self.current_position += 1;
-                self.current_pattern = self.module.playlist[self.current_position as usize];
-
-                if self.current_pattern == 255 {
-                    self.current_position = 0;
-                    self.current_pattern = self.module.playlist[self.current_position as usize];
+                // Together with a jump the break only picks the row
+                if !pos_jump_enabled {
+                    self.go_to_position(self.current_position as usize + 1, false);
                 }
             }
         }
 
         if self.current_row as usize == self.module.patterns[self.current_pattern as usize].len() {
             self.current_row = 0;
-            self.current_position += 1;
-            self.current_pattern = self.module.playlist[self.current_position as usize];
+            self.go_to_position(self.current_position as usize + 1, false);
+        };
+    }
+
+    // Moves to an order, skipping "+++" separators, the end of the song starts it over
+    fn go_to_position(&mut self, position: usize, jumped_back: bool) {
+        let next = playable_position(self.module, position);
+        self.current_position = next.unwrap_or(self.start_position);
+        self.current_pattern = self.module.playlist[self.current_position as usize];
+
+        if jumped_back || next.is_none() {
+            self.song_looped();
+        }
+    }
 
-            if self.current_pattern == 255 {
-                // End of song marker
-                std::process::exit(0);
+    fn song_looped(&mut self) {
+        self.loop_count += 1;
+
+        match self.end_of_song {
+            EndOfSong::Stop => self.ended = true,
+            EndOfSong::Loop(loops) => self.ended = self.loop_count > loops,
+            EndOfSong::LoopForever => {}
+            EndOfSong::FadeOut(seconds) => {
+                if self.fade.is_none() {
+                    let frames = ((seconds * self.samplerate as f32) as u32).max(1);
+                    self.fade = Some((frames, frames));
+                }
             }
-        };
+        }
+
+        if self.ended {
+            self.emit(EventKind::EndOfSong);
+        }
     }
 
     fn play_row(&mut self) {
-        let row = &self.module.patterns[self.current_pattern as usize][self.current_row as usize];
-
-        print!(
-            "Position {}, Pattern {}, Row {}\x1b[K\r",
-            self.current_position, self.current_pattern, self.current_row
-        );
-        stdout().flush().unwrap();
+        let module = self.module;
+        let row = &module.patterns[self.current_pattern as usize][self.current_row as usize];
 
         for (i, col) in row.iter().enumerate() {
             let channel = &mut self.channels[i];
 
-            /* match col.effect {
-                _ => {}
-                //TODO effects
-            } */
+            match col.effect {
+                Effect::SetSpeed(speed) if speed != 0 => self.current_speed = speed,
+                Effect::SetTempo(tempo) => self.current_tempo = tempo.max(32),
+                Effect::PatDelay(repeats) if self.row_repeats == 0 => self.row_repeats = repeats,
+                // Remember the slide, it is applied on the following ticks
+                Effect::DecTempo(value) if value != 0 => channel.tempo_memory = value,
+                Effect::IncTempo(value) => channel.tempo_memory = 0x10 | value,
+                Effect::HighOffset(value) => channel.high_offset = value,
+                Effect::GlissandoControl(enabled) => channel.glissando = enabled,
+                Effect::Vibrato(value) => {
+                    channel.set_vibrato(value >> 4, (value & 0x0F) * 4);
+                    channel.vibrato(true)
+                }
+                Effect::FineVibrato(value) => {
+                    channel.set_vibrato(value >> 4, value & 0x0F);
+                    channel.vibrato(true)
+                }
+                Effect::VolSlideVibrato(_) => channel.vibrato(true),
+                Effect::SetPan(value) => channel.set_panning(value as f32 * 64.0 / 15.0),
+                Effect::FineSetPan(value) => channel.set_panning(value as f32 / 4.0),
+                Effect::PastNoteCut => past_notes(&mut self.background, i).for_each(|v| v.cut()),
+                Effect::PastNoteOff => {
+                    past_notes(&mut self.background, i).for_each(|v| v.note_off())
+                }
+                Effect::PastNoteFade => {
+                    past_notes(&mut self.background, i).for_each(|v| v.note_fade())
+                }
+                _ => {} // TODO effects
+            }
 
             match col.vol {
                 // TODO volume commands
@@ -513,55 +1897,477 @@ impl Player<'_> {
                 VolEffect::PortaDown(_) => {}
                 VolEffect::PortaUp(_) => {}
                 VolEffect::TonePorta(_) => {}
-                VolEffect::VibratoDepth(_) => {}
-                VolEffect::SetPan(_) => {}
+                VolEffect::VibratoDepth(depth) => {
+                    channel.set_vibrato(0, depth * 4);
+                    channel.vibrato(true)
+                }
+                VolEffect::SetPan(pan) => channel.set_panning(pan as f32),
                 VolEffect::Volume(volume) => channel.volume = volume as f32,
             }
 
-            if col.instrument != 0 {
-                channel.current_sample_index = col.instrument - 1;
+            let tone_porta = matches!(
+                col.effect,
+                Effect::TonePorta(_) | Effect::VolSlideTonePorta(_)
+            ) || matches!(col.vol, VolEffect::TonePorta(_));
+
+            // The slide itself stays smooth, only the output is snapped, from tick 0 on
+            channel.snap_to_semitone = tone_porta && channel.glissando;
+
+            // In sample mode the instrument number is the sample,
+            // instruments pick the sample and the note to play from their note-sample table
+            let mut note = match col.note {
+                Note::On(note) => Some(note),
+                _ => None,
+            };
+            let mut sample_index = channel.current_sample_index;
+            match module.mode {
+                PlaybackMode::IT => {
+                    if col.instrument != 0 && (col.instrument as usize) <= module.instruments.len()
+                    {
+                        channel.current_instrument = Some(col.instrument - 1);
+                    }
+
+                    if let (Some(key), Some(instrument)) = (note, channel.instrument()) {
+                        note = match instrument.note_sample_table[key as usize] {
+                            (_, 0) => None, // Nothing is mapped to this note
+                            (mapped, sample) => {
+                                sample_index = sample - 1;
+                                Some(mapped)
+                            }
+                        };
+                    }
+                }
+                _ => {
+                    if col.instrument != 0 {
+                        sample_index = col.instrument - 1;
+                    }
+                }
+            }
+
+            // Samples the module doesn't have play nothing
+            let sample = module.samples.get(sample_index as usize);
+            if sample.is_none() {
+                note = None;
+            }
+
+            // After the note-sample table, so drum kits keep their samples
+            let transpose = self.playback_controls.transpose() as i16;
+            let note = note.map(|n| (n as i16 + transpose).clamp(0, 119) as u8);
+
+            if note.is_some() && !tone_porta && channel.playing {
+                self.move_to_background(i);
+            }
+            let channel = &mut self.channels[i];
+
+            if tone_porta {
+                // Compatible Gxx retriggers the envelopes when the portamento row has an instrument
+                if col.instrument != 0 && module.compatible_gxx {
+                    channel.restart_envelopes();
+                }
+                if matches!(module.mode, PlaybackMode::ITSample) && sample.is_some() {
+                    channel.current_sample_index = sample_index;
+                }
+            }
 
+            if let Some(sample) = sample.filter(|_| col.instrument != 0) {
                 if matches!(col.vol, VolEffect::None) {
-                    channel.volume = self.module.samples[channel.current_sample_index as usize]
-                        .default_volume as f32
+                    channel.volume = sample.default_volume as f32
+                }
+
+                if let Some(pan) = sample
+                    .default_pan
+                    .or_else(|| channel.instrument().and_then(|i| i.default_pan))
+                {
+                    channel.set_panning(pan as f32)
                 }
             }
 
             match col.note {
                 Note::None => {}
-                Note::On(note) => {
-                    if !matches!(col.effect, Effect::TonePorta(_))
-                        && !matches!(col.vol, VolEffect::TonePorta(_))
-                    {
-                        channel.playing = true;
-                        channel.position = match col.effect {
-                            Effect::SampleOffset(position) => {
-                                if position != 0 {
-                                    channel.offset_memory = position
-                                };
-                                channel.offset_memory as f64 * 256.0
+                Note::On(_) => {
+                    if let Some(note) = note {
+                        channel.last_note = note;
+
+                        if !tone_porta {
+                            channel.current_sample_index = sample_index;
+                            channel.trigger(note);
+                            channel.swing(&mut self.rng);
+                            if let Effect::SampleOffset(offset) = col.effect {
+                                channel.sample_offset(offset, self.out_of_range_offset);
                             }
-                            _ => 0.0,
-                        };
-                        channel.freq = 2f32.powf((note as f32 - 60.0) / 12.0)
-                            * self.module.samples[channel.current_sample_index as usize]
-                                .base_frequency as f32;
+                        }
                     }
                 }
-                Note::Fade => {}
-                Note::Cut => channel.playing = false,
-                Note::Off => channel.playing = false,
+                Note::Fade => channel.note_fade(),
+                Note::Cut => channel.cut(),
+                Note::Off => channel.note_off(),
+            }
+
+            // These apply to the note that is playing now, so they come after the trigger
+            match col.effect {
+                Effect::NNANoteCut => channel.new_note_action = NewNoteAction::Cut,
+                Effect::NNANoteContinue => channel.new_note_action = NewNoteAction::Continue,
+                Effect::NNANoteOff => channel.new_note_action = NewNoteAction::NoteOff,
+                Effect::NNANoteFade => channel.new_note_action = NewNoteAction::NoteFade,
+                Effect::VolEnvOff => channel.volume_envelope.enabled = false,
+                Effect::VolEnvOn => {
+                    channel.volume_envelope.enabled = channel.has_envelope(|i| &i.volume_envelope)
+                }
+                Effect::PanEnvOff => channel.pan_envelope.enabled = false,
+                Effect::PanEnvOn => {
+                    channel.pan_envelope.enabled = channel.has_envelope(|i| &i.pan_envelope)
+                }
+                Effect::PitchEnvOff => channel.pitch_envelope.enabled = false,
+                Effect::PitchEnvOn => {
+                    channel.pitch_envelope.enabled = channel.has_envelope(|i| &i.pitch_envelope)
+                }
+                _ => {}
+            }
+
+            let channel = i as u8;
+            match col.note {
+                Note::On(key) if note.is_some() && !tone_porta => self.emit(EventKind::NoteOn {
+                    channel,
+                    note: key,
+                    instrument: col.instrument,
+                }),
+                Note::Fade | Note::Cut | Note::Off => self.emit(EventKind::NoteOff { channel }),
+                _ => {}
             }
         }
     }
 }
 
-impl AudioCallback for Player<'_> {
-    type Channel = i32;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::module::{Column, Pattern};
+
+    fn sample(length: usize, loop_type: LoopType) -> Sample {
+        Sample {
+            base_frequency: 8363,
+            loop_type,
+            loop_start: 100,
+            loop_end: length as u32,
+            sustain_loop_type: LoopType::None,
+            sustain_loop_start: 0,
+            sustain_loop_end: 0,
+            default_volume: 64,
+            global_volume: 64,
+            default_pan: None,
+            audio: vec![0; length],
+        }
+    }
+
+    // Sample 0 is a 1000 frame one-shot, 1 the same looping from frame 100, 2 is long
+    fn module(old_effects: bool) -> Module {
+        Module {
+            name: String::new(),
+            mode: PlaybackMode::ITSample,
+            linear_freq_slides: true,
+            old_effects,
+            compatible_gxx: false,
+            initial_tempo: 125,
+            initial_speed: 6,
+            tempo_mode: TempoMode::Classic,
+            rows_per_beat: 4,
+            mixing_volume: 48,
+            channel_pan: vec![32; 64],
+            channel_enabled: vec![true; 64],
+            samples: vec![
+                sample(1000, LoopType::None),
+                sample(1000, LoopType::Forward),
+                sample(200000, LoopType::None),
+            ],
+            instruments: Vec::new(),
+            patterns: Vec::new(),
+            playlist: vec![255],
+        }
+    }
+
+    // Maps every note to itself and the given sample
+    fn instrument(sample: u8) -> Instrument {
+        Instrument {
+            new_note_action: NewNoteAction::Cut,
+            fadeout: 0,
+            global_volume: 128,
+            default_pan: None,
+            random_volume: 0,
+            random_pan: 0,
+            pitch_pan_separation: 0,
+            pitch_pan_center: 60,
+            note_sample_table: (0..120).map(|note| (note, sample)).collect(),
+            volume_envelope: Envelope::default(),
+            pan_envelope: Envelope::default(),
+            pitch_envelope: Envelope::default(),
+        }
+    }
+
+    fn column(note: Note, instrument: u8, effect: Effect) -> Column {
+        Column {
+            note,
+            instrument,
+            vol: VolEffect::None,
+            effect,
+        }
+    }
+
+    // One channel, all rows empty
+    fn pattern(rows: usize) -> Pattern {
+        (0..rows)
+            .map(|_| vec![column(Note::None, 0, Effect::None)])
+            .collect()
+    }
+
+    // Two empty patterns of 64 rows
+    fn song(playlist: &[u8]) -> Module {
+        let mut module = module(false);
+        module.patterns = vec![pattern(64), pattern(64)];
+        module.playlist = playlist.to_vec();
+        module
+    }
+
+    fn playing(module: &Module, sample: u8) -> Channel<'_> {
+        let mut channel = Channel::new(module, 0);
+        channel.current_sample_index = sample;
+        channel.playing = true;
+        channel
+    }
+
+    #[test]
+    fn offset_in_range() {
+        let module = module(false);
+        let mut channel = playing(&module, 0);
+
+        channel.sample_offset(0x02, OutOfRangeOffset::Ignore);
+        assert_eq!(channel.position, 512.0);
+        assert!(channel.playing);
+
+        // O00 reuses the last offset
+        channel.position = 0.0;
+        channel.sample_offset(0x00, OutOfRangeOffset::Ignore);
+        assert_eq!(channel.position, 512.0);
+    }
+
+    #[test]
+    fn high_offset_combines_with_offset() {
+        let module = module(false);
+        let mut channel = playing(&module, 2);
+
+        channel.high_offset = 1;
+        channel.sample_offset(0x02, OutOfRangeOffset::Ignore);
+        assert_eq!(channel.position, (65536 + 0x02 * 256) as f64);
+    }
+
+    #[test]
+    fn offset_past_end_ignore() {
+        let module = module(false);
+        let mut channel = playing(&module, 0);
+
+        channel.sample_offset(0x10, OutOfRangeOffset::Ignore);
+        assert_eq!(channel.position, 0.0);
+        assert!(channel.playing);
+    }
+
+    #[test]
+    fn offset_past_end_loop_start() {
+        let module = module(false);
+
+        let mut looped = playing(&module, 1);
+        looped.sample_offset(0x10, OutOfRangeOffset::LoopStart);
+        assert_eq!(looped.position, 100.0);
+        assert!(looped.playing);
+
+        let mut one_shot = playing(&module, 0);
+        one_shot.sample_offset(0x10, OutOfRangeOffset::LoopStart);
+        assert!(!one_shot.playing);
+    }
+
+    #[test]
+    fn offset_past_end_stop() {
+        let module = module(false);
+        let mut channel = playing(&module, 1);
+
+        channel.sample_offset(0x10, OutOfRangeOffset::Stop);
+        assert_eq!(channel.position, 1000.0);
+        assert!(!channel.playing);
+    }
+
+    #[test]
+    fn old_effects_default_to_stop() {
+        let old = module(true);
+        let new = module(false);
+
+        assert!(matches!(
+            Player::from_module(&old, 48000).out_of_range_offset,
+            OutOfRangeOffset::Stop
+        ));
+        assert!(matches!(
+            Player::from_module(&new, 48000).out_of_range_offset,
+            OutOfRangeOffset::Ignore
+        ));
+    }
+
+    #[test]
+    fn missing_sample_plays_nothing() {
+        let mut sample_mode = module(false);
+        sample_mode.patterns = vec![pattern(64)];
+        sample_mode.patterns[0][0][0] = column(Note::On(60), 9, Effect::None);
+        sample_mode.playlist = vec![0];
+
+        let mut instrument_mode = module(false);
+        instrument_mode.mode = PlaybackMode::IT;
+        instrument_mode.instruments = vec![instrument(9)];
+        instrument_mode.patterns = vec![pattern(64)];
+        instrument_mode.patterns[0][0][0] = column(Note::On(60), 1, Effect::None);
+        instrument_mode.playlist = vec![0];
+
+        for module in [&sample_mode, &instrument_mode] {
+            let mut player = Player::from_module(module, 48000);
+            player.render_stereo(&mut [0.0; 256]);
+            assert!(!player.channels[0].playing);
+            assert_eq!(player.channels[0].current_sample_index, 0);
+        }
+    }
+
+    #[test]
+    fn random_swing_follows_the_seed() {
+        let mut module = module(false);
+        module.mode = PlaybackMode::IT;
+        module.samples[2].audio = vec![8192; 200000];
+        module.instruments = vec![Instrument {
+            random_volume: 50,
+            random_pan: 32,
+            ..instrument(3)
+        }];
+        module.patterns = vec![(0..64)
+            .map(|_| vec![column(Note::On(60), 1, Effect::None)])
+            .collect()];
+        module.playlist = vec![0];
+
+        let render = |seed| {
+            let mut player = Player::from_module(&module, 48000);
+            player.set_seed(seed);
+            let mut out = vec![0.0; 48000 * 2];
+            player.render_stereo(&mut out);
+            out
+        };
+
+        assert_eq!(render(1), render(1));
+        assert_ne!(render(1), render(2));
+    }
+
+    #[test]
+    fn seek_skips_separators() {
+        let module = song(&[0, 254, 1]);
+        let mut player = Player::from_module(&module, 48000);
+
+        assert!(player.seek(2, 10));
+        assert_eq!(player.current_position(), 2);
+        assert_eq!(player.current_pattern(), 1);
+        assert_eq!(player.current_row(), 10);
+    }
+
+    #[test]
+    fn seek_to_unreachable_row_stays_at_start() {
+        let module = song(&[0, 255, 1]);
+        let mut player = Player::from_module(&module, 48000);
 
-    fn callback(&mut self, out: &mut [i32]) {
-        for s in out.iter_mut() {
-            *s = self.process();
+        assert!(!player.seek(2, 0));
+        assert_eq!(player.current_position(), 0);
+        assert_eq!(player.current_row(), 65535);
+
+        // The whole song takes 7.68 s
+        assert!(player.seek_time(Duration::from_secs(7)));
+        assert!(!player.seek_time(Duration::from_secs(8)));
+        assert_eq!(player.current_position(), 0);
+        assert_eq!(player.current_row(), 65535);
+    }
+
+    #[test]
+    fn seek_without_song() {
+        for playlist in [&[][..], &[255]] {
+            let module = song(playlist);
+            let mut player = Player::from_module(&module, 48000);
+
+            assert!(!player.seek(0, 0));
+            assert!(!player.seek_time(Duration::ZERO));
+        }
+    }
+
+    fn assert_secs(duration: Duration, secs: f64) {
+        assert!((duration.as_secs_f64() - secs).abs() < 1e-6, "{duration:?}");
+    }
+
+    #[test]
+    fn length_of_one_pattern() {
+        let module = song(&[0]);
+        let length = Player::from_module(&module, 48000).song_length();
+
+        // 64 rows of 6 ticks, 50 ticks per second at tempo 125
+        assert_secs(length.duration, 7.68);
+        assert_eq!((length.loop_position, length.loop_row), (0, 0));
+        assert_eq!(length.loop_time, Duration::ZERO);
+    }
+
+    #[test]
+    fn length_with_pattern_loop_and_jump() {
+        let mut module = song(&[0, 1]);
+        let rows = &mut module.patterns[1];
+        rows[0][0].effect = Effect::PatLoopStart;
+        rows[7][0].effect = Effect::PatLoop(2);
+        rows[7].push(column(Note::None, 0, Effect::PosJump(1)));
+        let length = Player::from_module(&module, 48000).song_length();
+
+        // Rows 0..=7 of the second pattern play three times before jumping back to its start
+        assert_secs(length.duration, 10.56);
+        assert_eq!((length.loop_position, length.loop_row), (1, 0));
+        assert_secs(length.loop_time, 7.68);
+    }
+
+    #[test]
+    fn length_without_song() {
+        for playlist in [&[][..], &[255]] {
+            let module = song(playlist);
+            let length = Player::from_module(&module, 48000).song_length();
+            assert_eq!(length.duration, Duration::ZERO);
         }
     }
+
+    #[test]
+    fn subsongs_split_at_end_markers() {
+        let module = song(&[0, 255, 1, 255]);
+        assert_eq!(Player::from_module(&module, 48000).subsongs(), [0, 2]);
+
+        // "+++" is skipped over, it doesn't end the song
+        let module = song(&[0, 254, 1, 255]);
+        assert_eq!(Player::from_module(&module, 48000).subsongs(), [0]);
+    }
+
+    #[test]
+    fn seek_reports_tempo_and_speed() {
+        let mut module = song(&[0]);
+        module.patterns[0][0][0].effect = Effect::SetSpeed(3);
+        let mut player = Player::from_module(&module, 48000);
+        let mut events = player.events(16);
+
+        assert!(player.seek(0, 4));
+        let kinds: Vec<_> = events.drain().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                EventKind::Position {
+                    position: 0,
+                    pattern: 0
+                },
+                EventKind::Row {
+                    position: 0,
+                    pattern: 0,
+                    row: 4
+                },
+                EventKind::Tempo(125),
+                EventKind::Speed(3),
+            ]
+        );
+    }
 }
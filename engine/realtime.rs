@@ -0,0 +1,72 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    backtrace::Backtrace,
+    cell::Cell,
+};
+
+// The render loop runs on the audio thread, where nothing may allocate, lock or do I/O.
+// Threads are flagged while they are in it, so an allocator can catch mistakes.
+thread_local! {
+    static RENDERING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Flags the current thread as rendering until dropped
+pub(crate) struct RenderGuard {
+    was_rendering: bool,
+}
+
+impl RenderGuard {
+    pub(crate) fn enter() -> RenderGuard {
+        RenderGuard {
+            was_rendering: RENDERING.with(|r| r.replace(true)),
+        }
+    }
+}
+
+impl Drop for RenderGuard {
+    fn drop(&mut self) {
+        RENDERING.with(|r| r.set(self.was_rendering));
+    }
+}
+
+// The system allocator with a debug assertion against allocating in the render loop.
+// Install it with #[global_allocator], in release builds it checks nothing.
+pub struct GuardedAllocator;
+
+fn check_not_rendering() {
+    // A panic in the render loop unwinds through allocations, that is reported already
+    if !cfg!(debug_assertions) || std::thread::panicking() {
+        return;
+    }
+
+    // Cleared first, as reporting allocates too. Allocators can't unwind, so this aborts.
+    if RENDERING.try_with(|r| r.replace(false)).unwrap_or(false) {
+        eprintln!(
+            "\nAllocation in the render loop\n{}",
+            Backtrace::force_capture()
+        );
+        std::process::abort();
+    }
+}
+
+unsafe impl GlobalAlloc for GuardedAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        check_not_rendering();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        check_not_rendering();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        check_not_rendering();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        check_not_rendering();
+        System.dealloc(ptr, layout)
+    }
+}
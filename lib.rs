@@ -0,0 +1,9 @@
+// Tracker module playback. Load a module with engine::format_it, then play or render it
+// with engine::player::Player, output has WAV writing and real-time backends.
+
+pub mod engine;
+pub mod output;
+
+pub use engine::format_it::{ITModule, NotAModuleError};
+pub use engine::module::{Module, ModuleInterface};
+pub use engine::player::Player;
@@ -1,12 +1,25 @@
-mod engine;
+use std::fs::File;
+use std::io::{self, stdout, BufWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-use engine::format_it::ITModule;
-use engine::player::{Interpolation, Player};
-
-use crate::engine::module::ModuleInterface;
+use modplayer::engine::events::{EventKind, EventReceiver};
+use modplayer::engine::interpolation::Interpolation;
+use modplayer::engine::module::TempoMode;
+use modplayer::engine::player::{EndOfSong, OutOfRangeOffset};
+#[cfg(debug_assertions)]
+use modplayer::engine::realtime::GuardedAllocator;
+use modplayer::output::wav::{WavFormat, WavWriter};
+use modplayer::output::{self, Backend};
+use modplayer::{ITModule, ModuleInterface, Player};
 
 use clap::Parser;
 
+// Aborts when the audio thread allocates
+#[cfg(debug_assertions)]
+#[global_allocator]
+static ALLOCATOR: GuardedAllocator = GuardedAllocator;
+
 #[derive(Parser, Debug)]
 #[command(name = "Rust module player")]
 #[command(about = "Very barebones tracker module player (IT samples only for now)")]
@@ -16,8 +29,92 @@ struct Args {
     #[arg(short, long, value_enum, default_value_t = Interpolation::Linear)]
     interpolation: Interpolation,
 
-    #[arg(short, long, default_value_t = 0)]
-    position: u8,
+    #[arg(short, long)]
+    position: Option<u8>,
+
+    /// Start this many seconds into the song, instead of at a position
+    #[arg(short, long, conflicts_with = "position")]
+    start: Option<f64>,
+
+    /// Override the module's tempo mode
+    #[arg(short, long, value_enum)]
+    tempo_mode: Option<TempoMode>,
+
+    /// How to handle sample offsets past the end of the sample
+    #[arg(short, long, value_enum)]
+    offset_mode: Option<OutOfRangeOffset>,
+
+    /// Seed for random volume and pan variation, for reproducible playback
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Output gain, defaults to the module's mixing volume scaled down for the channel count
+    #[arg(long)]
+    preamp: Option<f32>,
+
+    /// Soft limit peaks instead of letting them clip
+    #[arg(short, long)]
+    limiter: bool,
+
+    /// Frames to ramp volume and panning changes over, 0 to disable
+    #[arg(long)]
+    ramp: Option<u32>,
+
+    /// Times to repeat the song before stopping
+    #[arg(long)]
+    loops: Option<u32>,
+
+    /// Keep repeating the song until interrupted
+    #[arg(long, conflicts_with_all = ["loops", "fade_out"])]
+    forever: bool,
+
+    /// Fade out over this many seconds once the song ends
+    #[arg(long, conflicts_with = "loops")]
+    fade_out: Option<f32>,
+
+    /// Render to a WAV file as fast as possible instead of playing
+    #[arg(long)]
+    output: Option<String>,
+
+    /// Play one of the songs in the playlist, see --length for the list
+    #[arg(long)]
+    subsong: Option<usize>,
+
+    /// Print how long the song is and where it loops, then exit
+    #[arg(long)]
+    length: bool,
+
+    /// Sample format of the rendered file
+    #[arg(long, value_enum, default_value_t = WavFormat::Pcm16)]
+    format: WavFormat,
+
+    /// Also render every channel to its own file next to the output, e.g. song-ch01.wav
+    #[arg(long, requires = "output")]
+    stems: bool,
+
+    /// Silence these channels, e.g. --mute 3,5
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(1..=64))]
+    mute: Vec<u8>,
+
+    /// Only play these channels
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(1..=64))]
+    solo: Vec<u8>,
+
+    /// Shift every note by this many semitones
+    #[arg(long, allow_hyphen_values = true, default_value_t = 0)]
+    transpose: i8,
+
+    /// Multiply the pitch of everything, 2 is an octave up
+    #[arg(long, default_value_t = 1.0)]
+    pitch: f32,
+
+    /// Multiply the tempo, 2 plays twice as fast
+    #[arg(long, default_value_t = 1.0)]
+    tempo: f32,
+
+    /// Where to play to, stdout writes raw 32-bit float stereo PCM [default: sdl]
+    #[arg(long, value_enum)]
+    backend: Option<BackendKind>,
 }
 
 fn main() {
@@ -32,28 +129,244 @@ fn main() {
 
     let mut player: Player = Player::from_module(&binding, 48000);
     player.interpolation = args.interpolation;
-    player.current_position = args.position;
-    player.current_pattern = player.module.playlist[player.current_position as usize];
+    if let Some(tempo_mode) = args.tempo_mode {
+        player.tempo_mode = tempo_mode;
+    }
+    if let Some(offset_mode) = args.offset_mode {
+        player.out_of_range_offset = offset_mode;
+    }
+    if let Some(seed) = args.seed {
+        player.set_seed(seed);
+    }
+    if let Some(preamp) = args.preamp {
+        player.preamp = preamp;
+    }
+    player.limiter = args.limiter;
+    if let Some(ramp) = args.ramp {
+        player.volume_ramp = ramp;
+    }
+    player.end_of_song = match (args.loops, args.fade_out) {
+        _ if args.forever => EndOfSong::LoopForever,
+        (_, Some(seconds)) => EndOfSong::FadeOut(seconds),
+        (Some(loops), _) => EndOfSong::Loop(loops),
+        (None, None) => EndOfSong::Stop,
+    };
+    let playback = player.playback_controls();
+    playback.set_transpose(args.transpose);
+    playback.set_pitch_factor(args.pitch);
+    playback.set_tempo_factor(args.tempo);
+    let controls = player.controls();
+    for &channel in &args.mute {
+        controls.set_muted(channel as usize - 1, true);
+    }
+    for &channel in &args.solo {
+        controls.set_solo(channel as usize - 1, true);
+    }
+    if args.length {
+        let subsongs = player.subsongs();
+        println!();
+        for (i, &start) in subsongs.iter().enumerate() {
+            player.set_start_position(start);
+            let length = player.song_length();
+            println!(
+                "Subsong {} at position {}: {}, loops back to position {} row {} at {}",
+                i + 1,
+                start,
+                format_time(length.duration),
+                length.loop_position,
+                length.loop_row,
+                format_time(length.loop_time)
+            );
+        }
+        return;
+    }
 
-    let sdl_context = sdl2::init().unwrap();
-    let audio_subsystem = sdl_context.audio().unwrap();
+    if let Some(subsong) = args.subsong {
+        let subsongs = player.subsongs();
+        match subsong.checked_sub(1).and_then(|i| subsongs.get(i)) {
+            Some(&start) => {
+                player.set_start_position(start);
+            }
+            None => {
+                eprintln!("There are only {} subsongs", subsongs.len());
+                std::process::exit(1)
+            }
+        }
+    }
 
-    let spec = sdl2::audio::AudioSpecDesired {
-        freq: Some(48000),
-        channels: Some(1),
-        samples: Some(512),
+    let found = match (args.start, args.position) {
+        (Some(seconds), _) => player.seek_time(Duration::from_secs_f64(seconds)),
+        (None, Some(position)) => player.seek(position, 0),
+        (None, None) => true,
     };
+    if !found {
+        eprintln!("The song never gets there");
+        std::process::exit(1)
+    }
 
-    let device = audio_subsystem
-        .open_playback(None, &spec, |_| player)
-        .unwrap();
+    if let Some(path) = args.output {
+        if args.forever {
+            eprintln!("Can't render a song that never ends");
+            std::process::exit(1)
+        }
 
-    println!("Module name: {}", binding.name);
-    device.resume();
+        if let Err(e) = render_to_file(&mut player, &path, args.format, args.stems) {
+            eprintln!("Could not write {}: {}", path, e);
+            std::process::exit(1)
+        }
+        return;
+    }
+
+    if player.ended() {
+        return; // Nothing to play
+    }
+    // Builds without SDL can't play out loud, so they don't pick a silent backend on their own
+    let kind = args.backend.unwrap_or(BackendKind::Sdl);
+    let Some(mut backend) = kind.create() else {
+        eprintln!(
+            "Built without SDL, pick --backend null or stdout, or rebuild with --features sdl"
+        );
+        std::process::exit(1)
+    };
+
+    // Raw audio on stdout can't have text in between
+    let status = !matches!(kind, BackendKind::Stdout);
+    let events = player.events(1024);
 
     ctrlc::set_handler(move || std::process::exit(0)).expect("error listening to interrupt");
 
-    loop {}
+    // The end of song event can get dropped when the queue is full, so playing also signals it
+    let finished = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        if status {
+            println!("Module name: {}", binding.name);
+            scope.spawn(|| show_status(events, &finished));
+        }
+
+        let result = backend.play(&mut player);
+        finished.store(true, Ordering::Release);
+        if let Err(e) = result {
+            eprintln!("Could not play: {}", e);
+            std::process::exit(1)
+        }
+    });
+}
+
+// Follows playback through the player's events until the song ends, locking nothing
+fn show_status(mut events: EventReceiver, finished: &AtomicBool) {
+    loop {
+        // Checked first, so the last events still get shown
+        let done = finished.load(Ordering::Acquire);
+
+        for event in events.drain() {
+            match event.kind {
+                EventKind::Row {
+                    position,
+                    pattern,
+                    row,
+                } => {
+                    print!("Position {position}, Pattern {pattern}, Row {row}\x1b[K\r");
+                    stdout().flush().unwrap();
+                }
+                EventKind::EndOfSong => return,
+                _ => {}
+            }
+        }
+        if done {
+            return;
+        }
+
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum BackendKind {
+    Sdl,    // Needs the sdl feature
+    Null,   // Discards the audio, at the pace it would play
+    Stdout, // Raw interleaved stereo 32-bit float PCM
+}
+
+impl BackendKind {
+    // None if this build doesn't include it
+    fn create(self) -> Option<Box<dyn Backend>> {
+        match self {
+            #[cfg(feature = "sdl")]
+            BackendKind::Sdl => Some(Box::new(output::sdl::SdlBackend)),
+            #[cfg(not(feature = "sdl"))]
+            BackendKind::Sdl => None,
+            BackendKind::Null => Some(Box::new(output::null::NullBackend)),
+            BackendKind::Stdout => Some(Box::new(output::stdout::StdoutBackend)),
+        }
+    }
+}
+
+fn format_time(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{}:{:02}.{:03}",
+        millis / 60000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+fn create_wav(
+    path: &str,
+    format: WavFormat,
+    player: &Player,
+    title: &str,
+) -> io::Result<WavWriter<BufWriter<File>>> {
+    let file = BufWriter::new(File::create(path)?);
+    WavWriter::new(file, format, 2, player.samplerate, title)
+}
+
+fn render_to_file(
+    player: &mut Player,
+    path: &str,
+    format: WavFormat,
+    stems: bool,
+) -> io::Result<()> {
+    let name = player.module.name.clone();
+    let mut wav = create_wav(path, format, player, &name)?;
+
+    // Only the channels that play something get a file
+    let mut stem_files = Vec::new();
+    if stems {
+        let base = path.strip_suffix(".wav").unwrap_or(path);
+        for channel in player.module.used_channels() {
+            let stem_path = format!("{}-ch{:02}.wav", base, channel + 1);
+            let title = format!("{} (channel {})", name, channel + 1);
+            stem_files.push((channel, create_wav(&stem_path, format, player, &title)?));
+        }
+    }
+
+    let mut buffer = vec![0.0; 8192];
+    let mut stem_buffers = vec![vec![]; if stems { 64 } else { 0 }];
+    for stem in stem_files.iter().map(|(channel, _)| *channel) {
+        stem_buffers[stem] = vec![0.0; buffer.len()];
+    }
+
+    while !player.ended() {
+        let frames = if stems {
+            player.render_stems(&mut buffer, &mut stem_buffers)
+        } else {
+            player.render_stereo(&mut buffer)
+        };
+
+        wav.write(&buffer[..frames * 2])?;
+        for (channel, stem) in stem_files.iter_mut() {
+            stem.write(&stem_buffers[*channel][..frames * 2])?;
+        }
+    }
+
+    wav.finish()?;
+    for (_, stem) in stem_files {
+        stem.finish()?;
+    }
+
+    println!("\nRendered {}", path);
+    Ok(())
 }
 
 /* fn format_note(note: u8) -> String {
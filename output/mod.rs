@@ -0,0 +1,15 @@
+use std::io;
+
+use crate::engine::player::Player;
+
+pub mod null;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+pub mod stdout;
+pub mod wav;
+
+// Somewhere to play the player's output in real time
+pub trait Backend {
+    // Returns once the song has ended, the player's events can be followed on another thread meanwhile
+    fn play(&mut self, player: &mut Player) -> io::Result<()>;
+}
@@ -0,0 +1,30 @@
+use std::{
+    io,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::engine::player::Player;
+
+use super::Backend;
+
+// Renders and throws the audio away, as slowly as it would play, for machines without audio
+pub struct NullBackend;
+
+const BLOCK_FRAMES: usize = 512;
+
+impl Backend for NullBackend {
+    fn play(&mut self, player: &mut Player) -> io::Result<()> {
+        let mut buffer = vec![0.0; BLOCK_FRAMES * 2];
+        let start = Instant::now();
+        let mut frames = 0;
+
+        while !player.ended() {
+            frames += player.render_stereo(&mut buffer);
+
+            let due = start + Duration::from_secs_f64(frames as f64 / player.samplerate as f64);
+            sleep(due.saturating_duration_since(Instant::now()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use std::{io, thread::sleep, time::Duration};
+
+use sdl2::audio::{AudioCallback, AudioSpecDesired};
+
+use crate::engine::player::Player;
+
+use super::Backend;
+
+pub struct SdlBackend;
+
+struct Callback<'p, 'a>(&'p mut Player<'a>);
+
+impl AudioCallback for Callback<'_, '_> {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.0.render_stereo(out);
+    }
+}
+
+impl Backend for SdlBackend {
+    fn play(&mut self, player: &mut Player) -> io::Result<()> {
+        let sdl_context = sdl2::init().map_err(io::Error::other)?;
+        let audio_subsystem = sdl_context.audio().map_err(io::Error::other)?;
+
+        let spec = AudioSpecDesired {
+            freq: Some(player.samplerate as i32),
+            channels: Some(2),
+            samples: Some(512),
+        };
+
+        let mut device = audio_subsystem
+            .open_playback(None, &spec, |_| Callback(player))
+            .map_err(io::Error::other)?;
+        device.resume();
+
+        while !device.lock().0.ended() {
+            sleep(Duration::from_millis(100));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+use std::io::{self, Write};
+
+use crate::engine::player::Player;
+
+use super::Backend;
+
+// Raw interleaved stereo 32-bit float little endian PCM, paced by whatever reads it,
+// e.g. `modplayer song.it --backend stdout | aplay -f FLOAT_LE -c 2 -r 48000`
+pub struct StdoutBackend;
+
+const BLOCK_FRAMES: usize = 512;
+
+impl Backend for StdoutBackend {
+    fn play(&mut self, player: &mut Player) -> io::Result<()> {
+        let mut buffer = vec![0.0; BLOCK_FRAMES * 2];
+        let mut bytes = Vec::with_capacity(buffer.len() * 4);
+        let mut out = io::stdout().lock();
+
+        while !player.ended() {
+            let frames = player.render_stereo(&mut buffer);
+
+            bytes.clear();
+            for sample in &buffer[..frames * 2] {
+                bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+            out.write_all(&bytes)?;
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,195 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+#[derive(Default, Debug, Clone, Copy)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum WavFormat {
+    #[default]
+    Pcm16,
+    Pcm24,
+    Float,
+}
+
+impl WavFormat {
+    fn bytes_per_sample(self) -> u16 {
+        match self {
+            WavFormat::Pcm16 => 2,
+            WavFormat::Pcm24 => 3,
+            WavFormat::Float => 4,
+        }
+    }
+}
+
+// Streams samples to a WAV file, the chunk sizes are filled in by finish()
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    format: WavFormat,
+    channels: u16,
+    frames: u32,
+    fact_offset: Option<u64>, // Float files need a sample count in the fact chunk
+    data_offset: u64,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(
+        mut writer: W,
+        format: WavFormat,
+        channels: u16,
+        samplerate: u32,
+        title: &str,
+    ) -> io::Result<WavWriter<W>> {
+        let block_align = channels * format.bytes_per_sample();
+
+        writer.write_all(b"RIFF")?;
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        match format {
+            WavFormat::Float => {
+                writer.write_u32::<LittleEndian>(18)?;
+                writer.write_u16::<LittleEndian>(3)?; // WAVE_FORMAT_IEEE_FLOAT
+            }
+            _ => {
+                writer.write_u32::<LittleEndian>(16)?;
+                writer.write_u16::<LittleEndian>(1)?; // WAVE_FORMAT_PCM
+            }
+        }
+        writer.write_u16::<LittleEndian>(channels)?;
+        writer.write_u32::<LittleEndian>(samplerate)?;
+        writer.write_u32::<LittleEndian>(samplerate * block_align as u32)?;
+        writer.write_u16::<LittleEndian>(block_align)?;
+        writer.write_u16::<LittleEndian>(format.bytes_per_sample() * 8)?;
+
+        let mut fact_offset = None;
+        if let WavFormat::Float = format {
+            writer.write_u16::<LittleEndian>(0)?; // No extension
+
+            writer.write_all(b"fact")?;
+            writer.write_u32::<LittleEndian>(4)?;
+            fact_offset = Some(writer.stream_position()?);
+            writer.write_u32::<LittleEndian>(0)?;
+        }
+
+        if !title.is_empty() {
+            // Null terminated, chunks are padded to an even size
+            let mut name = title.as_bytes().to_vec();
+            name.push(0);
+            let padded = name.len() + name.len() % 2;
+
+            writer.write_all(b"LIST")?;
+            writer.write_u32::<LittleEndian>(4 + 8 + padded as u32)?;
+            writer.write_all(b"INFO")?;
+            writer.write_all(b"INAM")?;
+            writer.write_u32::<LittleEndian>(name.len() as u32)?;
+            writer.write_all(&name)?;
+            if padded > name.len() {
+                writer.write_u8(0)?;
+            }
+        }
+
+        writer.write_all(b"data")?;
+        writer.write_u32::<LittleEndian>(0)?;
+        let data_offset = writer.stream_position()?;
+
+        Ok(WavWriter {
+            writer,
+            format,
+            channels,
+            frames: 0,
+            fact_offset,
+            data_offset,
+        })
+    }
+
+    // Interleaved samples in -1..1, PCM clips anything louder while float keeps it
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            match self.format {
+                WavFormat::Pcm16 => self
+                    .writer
+                    .write_i16::<LittleEndian>((sample.clamp(-1.0, 1.0) * 32767.0).round() as i16)?,
+                WavFormat::Pcm24 => self.writer.write_i24::<LittleEndian>(
+                    (sample.clamp(-1.0, 1.0) * 8388607.0).round() as i32,
+                )?,
+                WavFormat::Float => self.writer.write_f32::<LittleEndian>(sample)?,
+            }
+        }
+
+        self.frames += (samples.len() / self.channels as usize) as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_size = self.frames * (self.channels * self.format.bytes_per_sample()) as u32;
+
+        // Chunks have to be an even size
+        if data_size & 1 != 0 {
+            self.writer.write_u8(0)?;
+        }
+        let end = self.writer.stream_position()?;
+
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_u32::<LittleEndian>(end as u32 - 8)?;
+
+        if let Some(offset) = self.fact_offset {
+            self.writer.seek(SeekFrom::Start(offset))?;
+            self.writer.write_u32::<LittleEndian>(self.frames)?;
+        }
+
+        self.writer.seek(SeekFrom::Start(self.data_offset - 4))?;
+        self.writer.write_u32::<LittleEndian>(data_size)?;
+
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn render(format: WavFormat, title: &str, samples: &[f32]) -> Vec<u8> {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), format, 1, 48000, title).unwrap();
+        wav.write(samples).unwrap();
+        wav.finish().unwrap().into_inner()
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn float_header() {
+        let bytes = render(WavFormat::Float, "ab", &[0.5, 2.0]);
+        assert_eq!(bytes.len(), 90);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4), 82);
+        assert_eq!(&bytes[38..42], b"fact");
+        assert_eq!(u32_at(&bytes, 46), 2);
+
+        // "ab" and its terminator are padded to 4 bytes
+        assert_eq!(&bytes[50..54], b"LIST");
+        assert_eq!(u32_at(&bytes, 54), 16);
+        assert_eq!(&bytes[58..66], b"INFOINAM");
+        assert_eq!(u32_at(&bytes, 66), 3);
+        assert_eq!(&bytes[70..74], b"ab\0\0");
+
+        assert_eq!(&bytes[74..78], b"data");
+        assert_eq!(u32_at(&bytes, 78), 8);
+        assert_eq!(f32::from_le_bytes(bytes[86..90].try_into().unwrap()), 2.0);
+    }
+
+    #[test]
+    fn odd_data_is_padded() {
+        let bytes = render(WavFormat::Pcm24, "", &[2.0]);
+        assert_eq!(bytes.len(), 48);
+        assert_eq!(u32_at(&bytes, 4), 40);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(&bytes, 40), 3);
+        assert_eq!(&bytes[44..48], &[0xff, 0xff, 0x7f, 0]);
+    }
+}
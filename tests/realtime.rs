@@ -0,0 +1,130 @@
+// Renders with the guarded allocator installed, which aborts the test run if the render loop
+// allocates. Only checks anything in debug builds.
+
+use std::time::Duration;
+
+use modplayer::engine::module::{
+    Column, Effect, Envelope, EnvelopeNode, Instrument, LoopType, Module, NewNoteAction, Note,
+    PlaybackMode, Sample, TempoMode, VolEffect,
+};
+use modplayer::engine::player::EndOfSong;
+use modplayer::engine::realtime::GuardedAllocator;
+use modplayer::Player;
+
+#[global_allocator]
+static ALLOCATOR: GuardedAllocator = GuardedAllocator;
+
+fn envelope(values: &[i8], sustain: bool) -> Envelope {
+    Envelope {
+        enabled: true,
+        loop_enabled: !sustain,
+        loop_start: 0,
+        loop_end: values.len() as u8 - 1,
+        sustain_enabled: sustain,
+        sustain_start: 1,
+        sustain_end: 1,
+        nodes: values
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| EnvelopeNode {
+                tick: i as u16 * 4,
+                value,
+            })
+            .collect(),
+    }
+}
+
+fn column(note: Note, instrument: u8, effect: Effect) -> Column {
+    Column {
+        note,
+        instrument,
+        vol: VolEffect::None,
+        effect,
+    }
+}
+
+// Notes on four channels that keep ringing in the background, with envelopes, fades and
+// past note effects
+fn module() -> Module {
+    let sample = Sample {
+        base_frequency: 8363,
+        loop_type: LoopType::Forward,
+        loop_start: 0,
+        loop_end: 1000,
+        sustain_loop_type: LoopType::None,
+        sustain_loop_start: 0,
+        sustain_loop_end: 0,
+        default_volume: 64,
+        global_volume: 64,
+        default_pan: None,
+        audio: (0..1000).map(|i| ((i % 100) * 300) as i16).collect(),
+    };
+    let instrument = Instrument {
+        new_note_action: NewNoteAction::Continue,
+        fadeout: 64,
+        global_volume: 128,
+        default_pan: None,
+        random_volume: 20,
+        random_pan: 8,
+        pitch_pan_separation: 4,
+        pitch_pan_center: 60,
+        note_sample_table: (0..120).map(|note| (note, 1)).collect(),
+        volume_envelope: envelope(&[64, 48, 32, 0], true),
+        pan_envelope: envelope(&[-16, 16], false),
+        pitch_envelope: envelope(&[0, 8, -8], false),
+    };
+
+    let pattern = (0..64)
+        .map(|row| {
+            (0..4)
+                .map(|channel| match (row + channel) % 8 {
+                    0 => column(Note::On(48 + row as u8 % 24), 1, Effect::None),
+                    3 => column(Note::Off, 0, Effect::Vibrato(0x44)),
+                    5 => column(Note::Fade, 0, Effect::PastNoteOff),
+                    6 => column(Note::None, 0, Effect::SetTempo(100 + row as u8)),
+                    _ => column(Note::None, 0, Effect::None),
+                })
+                .collect()
+        })
+        .collect();
+
+    Module {
+        name: String::from("realtime"),
+        mode: PlaybackMode::IT,
+        linear_freq_slides: true,
+        old_effects: false,
+        compatible_gxx: false,
+        initial_tempo: 125,
+        initial_speed: 6,
+        tempo_mode: TempoMode::Classic,
+        rows_per_beat: 4,
+        mixing_volume: 48,
+        channel_pan: vec![32; 64],
+        channel_enabled: vec![true; 64],
+        samples: vec![sample],
+        instruments: vec![instrument],
+        patterns: vec![pattern],
+        playlist: vec![0, 0],
+    }
+}
+
+#[test]
+fn render_loop_does_not_allocate() {
+    let module = module();
+    let mut player = Player::from_module(&module, 48000);
+    player.end_of_song = EndOfSong::FadeOut(1.0);
+    let mut events = player.events(64);
+    let controls = player.controls();
+    let mut block = vec![0.0; 512 * 2];
+    let mut stems = vec![vec![0.0; 512 * 2]; 4];
+
+    assert!(player.seek_time(Duration::from_secs(2)));
+    let mut heard = 0;
+    while !player.ended() {
+        controls.set_muted(1, heard % 2 == 0);
+        player.render_stereo(&mut block);
+        player.render_stems(&mut block, &mut stems);
+        heard += events.drain().count();
+    }
+    assert!(heard > 0);
+}